@@ -1,6 +1,11 @@
 use std::path::PathBuf;
 
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Args, Parser, ValueEnum};
+
+use crate::{
+    config::{Strategy, Symlinks},
+    repository_info::Finding,
+};
 
 #[derive(Parser, Debug)]
 #[clap(
@@ -14,6 +19,19 @@ pub struct CliArguments {
     #[clap(short, long, action = ArgAction::Count)]
     pub verbose: u8,
 
+    /// Use this config file instead of the default one, e.g. to keep separate profiles.
+    #[clap(long, global = true, env = "GEIL_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Use this state file instead of the default one.
+    #[clap(long, global = true, env = "GEIL_STATE")]
+    pub state: Option<PathBuf>,
+
+    /// Don't look for new repositories in watched directories and don't forget removed ones.
+    /// Useful for quick runs, if there haven't been any changes. See `geil scan`.
+    #[clap(long, global = true)]
+    pub no_scan: bool,
+
     #[clap(subcommand)]
     pub cmd: SubCommand,
 }
@@ -42,28 +60,152 @@ pub enum SubCommand {
         directories: Vec<PathBuf>,
     },
 
+    /// Watch one or more directories for repositories.
+    ///
+    /// All repositories inside a watched directory are automatically discovered every time
+    /// `geil` runs. By default, repositories are searched up to 5 levels deep.
+    /// The watch is local to this machine.
+    Watch {
+        /// The directories that should be watched
+        directories: Vec<PathBuf>,
+
+        /// List all watched directories
+        #[clap(short, long)]
+        list: bool,
+
+        /// How many directories deep repositories are searched for.
+        #[clap(short, long)]
+        max_depth: Option<usize>,
+
+        /// Whether symlinked directories are searched as well.
+        #[clap(long)]
+        symlinks: Option<Symlinks>,
+    },
+
+    /// Look for new repositories in all watched directories and forget removed ones.
+    ///
+    /// This happens automatically before most commands, unless `--no-scan` is passed.
+    /// Directories that didn't change since the last scan aren't searched again.
+    Scan,
+
+    /// Stop watching one or more directories.
+    ///
+    /// All repositories that were only known through that directory will be forgotten.
+    /// Repositories that have been added via `geil add` are kept.
+    Unwatch {
+        /// The directories that should no longer be watched
+        directories: Vec<PathBuf>,
+    },
+
+    /// Manage the SSH keys that should be loaded into `ssh-agent` before updating.
+    Keys {
+        #[clap(subcommand)]
+        cmd: KeysCommand,
+    },
+
+    /// Write a manifest of all known repositories.
+    ///
+    /// The manifest contains the remotes, default branch and hook of each repository.
+    /// Paths are stored relative to their watched directory.
+    /// Use `geil clone` to clone all repositories from a manifest on another machine.
+    Export {
+        /// The file the manifest should be written to. Printed to stdout if omitted.
+        path: Option<PathBuf>,
+
+        #[clap(flatten)]
+        filter: FilterArgs,
+    },
+
+    /// Clone all repositories from a manifest that don't exist on this machine yet.
+    ///
+    /// Repositories are only cloned into watched directories. They're matched by their path
+    /// or, if there's no such watched directory, their name.
+    /// Cloned repositories are automatically added to your known repositories.
+    #[clap(alias = "import")]
+    Clone {
+        /// The manifest created by `geil export`
+        manifest: PathBuf,
+
+        /// Register the hooks of the manifest, which are executed after every update.
+        /// Only use this for manifests you trust, hooks can run arbitrary commands.
+        #[clap(long)]
+        with_hooks: bool,
+
+        /// Don't clone repositories in parallel
+        #[clap(short, long)]
+        not_parallel: bool,
+
+        /// The amount of threads that should run in parallel for cloning repositories.
+        #[clap(short, long)]
+        threads: Option<usize>,
+    },
+
+    /// Run an arbitrary command in all repositories.
+    ///
+    /// The arguments are passed to the program as they are, e.g.
+    /// `geil exec --filter '*-service' -- git commit -m "Update dependencies"`.
+    /// Pass `--shell` to execute the command via the shell instead, which allows pipes and
+    /// chaining, e.g. `geil exec --shell 'cargo build && cargo test'`.
+    Exec {
+        #[clap(flatten)]
+        filter: FilterArgs,
+
+        /// Only run in repositories with this finding. Can be passed multiple times.
+        /// All repositories are checked first, if this is used.
+        #[clap(long, value_enum)]
+        finding: Vec<Finding>,
+
+        #[clap(flatten)]
+        args: ExecArgs,
+    },
+
     /// Print information about the current configuration of geil.
-    Info,
+    Info {
+        #[clap(flatten)]
+        filter: FilterArgs,
+    },
 
     /// This is the main command of `geil`.
     ///
     /// - Fetch all branches from a remote
     /// - Check stash sizes
     /// - Check for local changes
-    /// - Update via fast-forward if possible
+    /// - Update via fast-forward if possible, or via another configured strategy
     Update {
-        /// Show all repositories and not only those that are somehow interesting
-        #[clap(short, long)]
-        all: bool,
+        #[clap(flatten)]
+        args: RunArgs,
 
-        /// Don't run repository checks in parallel
-        /// This is useful in combination with the verbose flag for debugging.
+        #[clap(flatten)]
+        update: UpdateArgs,
+    },
+
+    /// Delete local branches that aren't needed anymore.
+    ///
+    /// All repositories are fetched with `--prune` first. Afterwards, local branches whose
+    /// upstream is gone or that're fully merged into the default branch of their remote are
+    /// listed and deleted after confirmation. Checked out branches are never deleted.
+    /// Branches with commits that aren't part of the default branch are only deleted with
+    /// `--force`.
+    Prune {
+        #[clap(flatten)]
+        filter: FilterArgs,
+
+        /// Only list the branches that would be deleted.
         #[clap(short, long)]
-        not_parallel: bool,
+        dry_run: bool,
 
-        /// The amount of threads that should run in parallel for checking repositories.
+        /// Delete the branches without asking for confirmation.
         #[clap(short, long)]
-        threads: Option<usize>,
+        yes: bool,
+
+        /// Also delete branches whose upstream is gone, but whose commits aren't part of the
+        /// default branch, e.g. because they've been squash merged. Those commits are lost.
+        #[clap(long)]
+        force: bool,
+
+        /// Don't fetch before looking for branches.
+        #[clap(long)]
+        no_fetch: bool,
     },
 
     /// Only check for local changes.
@@ -71,17 +213,183 @@ pub enum SubCommand {
     /// - Check stash sizes
     /// - Check for local changes
     Check {
-        /// Show all repositories and not only those that are somehow interesting
-        #[clap(short, long)]
-        all: bool,
+        #[clap(flatten)]
+        args: RunArgs,
+    },
+}
+
+/// Options shared by all commands that check repositories.
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    /// Show all repositories and not only those that are somehow interesting
+    #[clap(short, long)]
+    pub all: bool,
+
+    /// Don't run repository checks in parallel
+    /// This is useful in combination with the verbose flag for debugging.
+    #[clap(short, long)]
+    pub not_parallel: bool,
+
+    /// The amount of threads that should run in parallel for checking repositories.
+    #[clap(short, long)]
+    pub threads: Option<usize>,
+
+    /// The output format of the results.
+    /// Machine-readable formats always contain all repositories.
+    #[clap(short, long, value_enum, default_value_t = OutputFormat::Table)]
+    pub output: OutputFormat,
+
+    /// Treat repositories that only contain untracked files as clean.
+    #[clap(short = 'u', long)]
+    pub ignore_untracked: bool,
+
+    /// Allow git to ask for credentials or host key confirmations.
+    /// This is the default, if the repositories aren't handled in parallel.
+    #[clap(long, conflicts_with = "non_interactive")]
+    pub interactive: bool,
+
+    /// Let git fail, instead of asking for credentials or host key confirmations.
+    /// This is the default, if the repositories are handled in parallel.
+    #[clap(long)]
+    pub non_interactive: bool,
+
+    /// The maximum time (in seconds) a single git command may take.
+    /// Overrides the timeout from the config file.
+    #[clap(long)]
+    pub command_timeout: Option<u64>,
+
+    /// The maximum time (in seconds) all git commands for a single repository may take.
+    /// Overrides the timeout from the config file.
+    #[clap(long)]
+    pub repository_timeout: Option<u64>,
+
+    #[clap(flatten)]
+    pub filter: FilterArgs,
+
+    /// Only handle repositories inside of these directories.
+    /// If a path lies inside of a repository, that repository is handled.
+    /// For example, `geil update .` only updates the repository you're currently in.
+    pub paths: Vec<PathBuf>,
+}
+
+impl RunArgs {
+    /// Whether git may interact with the user via the terminal.
+    /// Prompts would be hidden by the progress bars of parallel runs.
+    pub fn interactive(&self) -> bool {
+        (self.not_parallel || self.interactive) && !self.non_interactive
+    }
+}
+
+/// Options for running arbitrary commands in repositories.
+#[derive(Args, Debug)]
+pub struct ExecArgs {
+    /// Print the output of the commands as soon as it's available, prefixed by the
+    /// repository name.
+    #[clap(short, long)]
+    pub stream: bool,
+
+    /// Execute the command via the shell. Multiple arguments are joined by spaces.
+    #[clap(long)]
+    pub shell: bool,
+
+    /// Don't run the command in parallel
+    #[clap(short, long)]
+    pub not_parallel: bool,
+
+    /// The amount of threads that should run in parallel.
+    #[clap(short, long)]
+    pub threads: Option<usize>,
+
+    /// The maximum time (in seconds) the command may take in a single repository.
+    /// Overrides the timeout from the config file.
+    #[clap(long)]
+    pub command_timeout: Option<u64>,
 
-        /// Don't run repository checks in parallel
-        /// This is useful in combination with the verbose flag for debugging.
+    /// The maximum time (in seconds) all commands for a single repository may take.
+    /// This includes checking the repository, if it's filtered by findings.
+    /// Overrides the timeout from the config file.
+    #[clap(long)]
+    pub repository_timeout: Option<u64>,
+
+    /// The command that should be executed
+    #[clap(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+    pub command: Vec<String>,
+}
+
+/// Options that only apply to updating repositories.
+#[derive(Args, Debug)]
+pub struct UpdateArgs {
+    /// Fast-forward all local branches with an upstream, not only the checked out one.
+    /// Overrides the config file.
+    #[clap(long)]
+    pub all_branches: bool,
+
+    /// How the checked out branch is updated. Overrides the config file.
+    #[clap(short, long, value_enum)]
+    pub strategy: Option<Strategy>,
+
+    /// Check out the default branch, if the checked out branch has been merged into it.
+    /// Repositories with local changes are left alone.
+    #[clap(long)]
+    pub switch_merged: bool,
+
+    /// Delete remote refs that no longer exist on the remote while fetching.
+    /// Overrides the config file.
+    #[clap(long)]
+    pub prune: bool,
+}
+
+/// Options to restrict the set of repositories a command works on.
+#[derive(Args, Debug)]
+pub struct FilterArgs {
+    /// Only include repositories whose path matches this glob, e.g. `*-service`.
+    /// Prefix the pattern with `re:` to use a regular expression instead.
+    /// Can be passed multiple times.
+    #[clap(short, long)]
+    pub filter: Vec<String>,
+
+    /// Only include repositories of this group. Can be passed multiple times.
+    #[clap(short, long)]
+    pub group: Vec<String>,
+
+    /// Exclude all repositories of this group. Can be passed multiple times.
+    #[clap(short = 'G', long)]
+    pub exclude_group: Vec<String>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum OutputFormat {
+    /// A human-readable table
+    Table,
+    Json,
+    Yaml,
+}
+
+#[derive(Parser, Debug)]
+pub enum KeysCommand {
+    /// Add a private key to the list of known keys.
+    ///
+    /// The public key is expected to be next to it with a `.pub` extension.
+    Add {
+        /// The path to the private key
+        path: PathBuf,
+
+        /// The name of the key. Defaults to the filename of the key.
         #[clap(short, long)]
-        not_parallel: bool,
+        name: Option<String>,
 
-        /// The amount of threads that should run in parallel for checking repositories.
+        /// Require the key to exist on every machine.
+        /// Keys are optional by default, i.e. they're skipped if they don't exist.
         #[clap(short, long)]
-        threads: Option<usize>,
+        required: bool,
+    },
+
+    /// Remove one or more keys from the list of known keys.
+    Remove {
+        /// The names of the keys that should be removed
+        names: Vec<String>,
     },
+
+    /// List all known keys, their fingerprint and whether they're loaded in `ssh-agent`.
+    List,
 }
@@ -22,10 +22,26 @@ pub fn add(state: &mut State, repos: Vec<PathBuf>) -> Result<()> {
         }
 
         // Store the absolute path.
+        // Repositories that are already known, e.g. via a watched directory, are marked as
+        // added, so they're kept when that directory is unwatched.
         let real_path = std::fs::canonicalize(&path)?;
-        if !state.has_repo_at_path(&real_path) {
-            println!("Added repository: {:?}", &real_path);
-            state.repositories.push(Repository::new(real_path));
+        match state
+            .repositories
+            .iter_mut()
+            .find(|repo| repo.path == real_path)
+        {
+            Some(repo) if repo.added => (),
+            Some(repo) => {
+                println!("Added repository: {:?}", &real_path);
+                repo.added = true;
+            }
+            None => {
+                println!("Added repository: {:?}", &real_path);
+                state.repositories.push(Repository {
+                    added: true,
+                    ..Repository::new(real_path)
+                });
+            }
         }
     }
     state.save()
@@ -1,77 +1,76 @@
-use std::{
-    collections::HashMap,
-    env::vars,
-    time::{Duration, Instant},
-};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{Result, bail};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use log::debug;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
 use crate::{
+    cli::RunArgs,
     config::GeilConfig,
     display::{multi_progress_bar, print_status},
-    git::{check_local_changes, check_unpushed_commits, get_stashed_entries},
-    repository_info::{RepositoryInfo, RepositoryState},
+    filter::RepositoryFilter,
+    git::{
+        GitBackend, backend, check_default_branch, check_local_changes, check_unpushed_commits,
+        environment, get_stashed_entries,
+    },
+    process::{handle_interrupts, interrupted, set_interactive},
+    repository_info::{Finding, RepositoryInfo, UpdateOutcome},
     state::State,
 };
 
-pub fn check(
-    state: &mut State,
-    config: &GeilConfig,
-    show_all: bool,
-    parallel: bool,
-    threads: Option<usize>,
-) -> Result<()> {
-    let repo_infos = state.repo_infos_by_wall_time(config);
+pub fn check(state: &mut State, config: &GeilConfig, args: &RunArgs) -> Result<()> {
+    let repositories = state.repositories.iter().map(|repo| repo.path.as_path());
+    let filter = RepositoryFilter::new(config, &args.filter)?.paths(&args.paths, repositories)?;
+    let repo_infos = state.repo_infos_by_wall_time(config, &filter);
+    let ignore_untracked = args.ignore_untracked;
+    let timeouts = config
+        .timeouts
+        .with_overrides(args.command_timeout, args.repository_timeout);
 
-    // Save all environment variables for later injection into git
-    let mut envs = HashMap::new();
-    for (key, value) in vars() {
-        envs.insert(key, value);
-    }
+    // Stop all running git processes on Ctrl-C, but still show the results so far.
+    handle_interrupts()?;
+
+    let interactive = args.interactive();
+    set_interactive(interactive);
+    let git = backend(config.backend, environment(interactive))?;
 
     let (multi_progress, main_bar) = multi_progress_bar(repo_infos.len() as u64)?;
 
-    let repo_infos = if parallel {
+    let repo_infos = if !args.not_parallel {
         // Set the amount of threads, if specified.
-        if let Some(threads) = threads {
+        if let Some(threads) = args.threads {
             rayon::ThreadPoolBuilder::new()
                 .num_threads(threads)
                 .build_global()
                 .unwrap();
         }
 
-        let results: Result<Vec<RepositoryInfo>> = repo_infos
+        let results: Vec<RepositoryInfo> = repo_infos
             .into_par_iter()
             .map(|mut repo_info| {
                 // Handle the repository and track execution time.
                 let start = Instant::now();
-                repo_info = match check_repo(&multi_progress, repo_info, &envs) {
-                    Ok(repo_info) => repo_info,
-                    Err(err) => {
-                        // Make sure the bar gets incremented even if we get an error.
-                        main_bar.inc(1);
-                        return Err(err);
-                    }
-                };
+                repo_info.start_timeouts(&timeouts);
+                repo_info = check_repo(&multi_progress, repo_info, git.as_ref(), ignore_untracked);
                 repo_info.check_time = Some(start.elapsed().as_millis() as usize);
 
                 main_bar.inc(1);
-                Ok(repo_info)
+                repo_info
             })
             .collect();
 
         main_bar.finish_with_message("All done: ");
 
-        results?
+        results
     } else {
         let mut results = Vec::new();
-        for repo_info in repo_infos.into_iter() {
+        for mut repo_info in repo_infos.into_iter() {
             // Handle the repository and track execution time.
             let start = Instant::now();
-            let mut repo_info = check_repo(&multi_progress, repo_info, &envs)?;
+            repo_info.start_timeouts(&timeouts);
+            let mut repo_info =
+                check_repo(&multi_progress, repo_info, git.as_ref(), ignore_untracked);
             repo_info.check_time = Some(start.elapsed().as_millis() as usize);
 
             debug!("Check took {}ms", start.elapsed().as_millis());
@@ -87,19 +86,44 @@ pub fn check(
 
     state.update_check_times(&repo_infos)?;
 
-    print_status(repo_infos, show_all)?;
+    let failed = count_failed(&repo_infos);
+    print_status(repo_infos, args.all, args.output)?;
+
+    if interrupted() {
+        bail!("The run has been interrupted, the results are incomplete");
+    }
+
+    if failed > 0 {
+        bail!("Failed to check {failed} repositories");
+    }
 
     Ok(())
 }
 
+/// Count the repositories that ran into an error.
+pub fn count_failed(repo_infos: &[RepositoryInfo]) -> usize {
+    repo_infos
+        .iter()
+        .filter(|info| {
+            info.has(Finding::Error)
+                || info.has(Finding::TimedOut)
+                || info.has(Finding::AuthenticationRequired)
+        })
+        .count()
+}
+
 /// This is a simple wrapper around the actual repo check function
 /// for easier progress bar handling.
+///
+/// Any error is recorded on the [RepositoryInfo], so a single failing repository
+/// doesn't abort the whole run.
 pub fn check_repo(
     multi_progress: &MultiProgress,
-    repo_info: RepositoryInfo,
-    envs: &HashMap<String, String>,
-) -> Result<RepositoryInfo> {
-    let mut bar = ProgressBar::new(3);
+    mut repo_info: RepositoryInfo,
+    git: &dyn GitBackend,
+    ignore_untracked: bool,
+) -> RepositoryInfo {
+    let mut bar = ProgressBar::new(4);
     let spinner_style =
         ProgressStyle::with_template("{duration} {spinner} {prefix:.bold.white.dim} - {wide_msg}")
             .unwrap()
@@ -113,38 +137,43 @@ pub fn check_repo(
     bar.enable_steady_tick(Duration::from_millis(125));
 
     // Run the actual repo handling logic.
-    let result = check_repo_inner(&bar, repo_info, envs);
+    if let Err(err) = check_repo_inner(&bar, &mut repo_info, git, ignore_untracked) {
+        repo_info.set_error(err);
+    }
 
     // Clean up this repo's progress bar.
     bar.disable_steady_tick();
     bar.finish();
     multi_progress.remove(&bar);
 
-    result
+    repo_info
 }
 
 pub fn check_repo_inner(
     bar: &ProgressBar,
-    mut repo_info: RepositoryInfo,
-    envs: &HashMap<String, String>,
-) -> Result<RepositoryInfo> {
+    repo_info: &mut RepositoryInfo,
+    git: &dyn GitBackend,
+    ignore_untracked: bool,
+) -> Result<()> {
     let name = repo_info.name.clone();
 
-    // Default to a `Ok` repo state.
-    // If anything is not ok, it'll be set in the respective function.
-    repo_info.state = RepositoryState::Ok;
-
-    bar.set_prefix(format!("[1/3] - {name}"));
+    bar.set_prefix(format!("[1/4] - {name}"));
     bar.set_message(format!("{name}: Checking stash"));
-    get_stashed_entries(&mut repo_info, envs)?;
+    get_stashed_entries(repo_info, git)?;
 
-    bar.set_prefix(format!("[2/3] - {name}"));
+    bar.set_prefix(format!("[2/4] - {name}"));
     bar.set_message(format!("{name}: Check for local changes"));
-    check_local_changes(&mut repo_info, envs)?;
+    check_local_changes(repo_info, git, ignore_untracked)?;
 
-    bar.set_prefix(format!("[3/3] - {name}"));
+    bar.set_prefix(format!("[3/4] - {name}"));
     bar.set_message(format!("{name}: Check for unpushed commits"));
-    check_unpushed_commits(&mut repo_info, envs)?;
+    check_unpushed_commits(repo_info, git)?;
+
+    bar.set_prefix(format!("[4/4] - {name}"));
+    bar.set_message(format!("{name}: Compare with default branch"));
+    check_default_branch(repo_info, git, false)?;
 
-    Ok(repo_info)
+    repo_info.outcome = UpdateOutcome::Checked;
+
+    Ok(())
 }
@@ -0,0 +1,180 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use anyhow::{Result, bail};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use log::error;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    config::{GeilConfig, Hook},
+    display::multi_progress_bar,
+    git,
+    manifest::{Manifest, ManifestEntry},
+    process::{handle_interrupts, set_interactive},
+    state::{Repository, State},
+};
+
+/// Clone all repositories of a manifest that don't exist on this machine yet.
+/// The cloned repositories are added to the list of known repositories.
+///
+/// Repositories are only cloned into directories that're watched on this machine, see
+/// [ManifestEntry::target_path].
+///
+/// The hooks of the manifest are only registered with `with_hooks`, as they're executed after
+/// every update.
+pub fn clone(
+    state: &mut State,
+    config: &GeilConfig,
+    manifest: &Path,
+    with_hooks: bool,
+    parallel: bool,
+    threads: Option<usize>,
+) -> Result<()> {
+    let manifest = Manifest::load(manifest)?;
+    let watched = state.watched_paths(config);
+
+    // Only clone repositories that don't exist yet.
+    let mut failed = 0;
+    let mut entries = Vec::new();
+    for entry in manifest.repositories {
+        let path = match entry.target_path(&watched) {
+            Ok(path) => path,
+            Err(err) => {
+                error!("Skipping invalid manifest entry: {err:#}");
+                failed += 1;
+                continue;
+            }
+        };
+        if path.exists() {
+            println!("Skipping existing repository: {path:?}");
+            continue;
+        }
+        entries.push((entry, path));
+    }
+
+    if entries.is_empty() {
+        if failed > 0 {
+            bail!("Failed to clone {failed} repositories");
+        }
+        println!("Nothing to do here, all repositories already exist.");
+        return Ok(());
+    }
+
+    // Prompts would be hidden by the progress bars of parallel runs.
+    let interactive = !parallel;
+    set_interactive(interactive);
+    let envs = git::environment(interactive);
+
+    // Stop all running clones on Ctrl-C, but still register the finished ones.
+    handle_interrupts()?;
+
+    let (multi_progress, main_bar) = multi_progress_bar(entries.len() as u64)?;
+
+    let results: Vec<(ManifestEntry, PathBuf, Result<()>)> = if parallel {
+        // Set the amount of threads, if specified.
+        if let Some(threads) = threads {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build_global()
+                .unwrap();
+        }
+
+        let results = entries
+            .into_par_iter()
+            .map(|(entry, path)| {
+                let result = clone_repo(&multi_progress, &entry, &path, &envs);
+                main_bar.inc(1);
+                (entry, path, result)
+            })
+            .collect();
+
+        main_bar.finish_with_message("All done: ");
+
+        results
+    } else {
+        let mut results = Vec::new();
+        for (entry, path) in entries.into_iter() {
+            let result = clone_repo(&multi_progress, &entry, &path, &envs);
+            main_bar.inc(1);
+            results.push((entry, path, result));
+        }
+
+        results
+    };
+
+    // Finish and clean up the progress bar
+    main_bar.finish();
+    let _ = multi_progress.clear();
+
+    for (entry, path, result) in results {
+        if let Err(err) = result {
+            error!("Failed to clone repository {path:?}: {err:#}");
+            failed += 1;
+            continue;
+        }
+
+        println!("Cloned repository: {path:?}");
+        if !state.has_repo_at_path(&path) {
+            state.repositories.push(Repository::new(path.clone()));
+        }
+
+        // Hooks run arbitrary commands, so they're only taken from trusted manifests.
+        let Some(command) = entry.hook else {
+            continue;
+        };
+        if !with_hooks {
+            println!("Skipping hook, pass --with-hooks to register it: {command}");
+            continue;
+        }
+        // Register the hook, unless there's already one for this repository.
+        if !state.hooks(config).iter().any(|hook| hook.path() == path) {
+            println!("Registered hook: {command}");
+            state.hooks.push(Hook::new(path, command));
+        }
+    }
+
+    state.save()?;
+
+    if failed > 0 {
+        bail!("Failed to clone {failed} repositories");
+    }
+
+    Ok(())
+}
+
+/// This is a simple wrapper around the actual clone function
+/// for easier progress bar handling.
+pub fn clone_repo(
+    multi_progress: &MultiProgress,
+    entry: &ManifestEntry,
+    path: &Path,
+    envs: &HashMap<String, String>,
+) -> Result<()> {
+    let mut bar = ProgressBar::new(1);
+    let spinner_style =
+        ProgressStyle::with_template("{duration} {spinner} {prefix:.bold.white.dim} - {wide_msg}")
+            .unwrap()
+            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ");
+    bar.set_style(spinner_style);
+
+    // Add the bar to the end of the multi_bar.
+    bar = multi_progress.add(bar);
+
+    // Enable a steady tick after adding it to the bar, to ensure correct position rendering.
+    bar.enable_steady_tick(Duration::from_millis(125));
+
+    bar.set_prefix(format!("[1/1] - {}", path.to_string_lossy()));
+    bar.set_message("Cloning repository");
+    let result = git::clone(entry, path, envs);
+
+    // Clean up this repo's progress bar.
+    bar.disable_steady_tick();
+    bar.finish();
+    multi_progress.remove(&bar);
+
+    result
+}
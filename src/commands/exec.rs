@@ -0,0 +1,242 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, bail};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use log::debug;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    cli::ExecArgs,
+    commands::check_repo_inner,
+    config::GeilConfig,
+    display::{multi_progress_bar, print_exec_results},
+    filter::RepositoryFilter,
+    git::{GitBackend, backend, command_environment, environment, repository_environment},
+    process::{Cmd, handle_interrupts, interrupted, set_interactive},
+    repository_info::RepositoryInfo,
+    state::State,
+};
+
+/// The result of running a command inside a single repository.
+pub struct ExecResult {
+    pub path: PathBuf,
+    /// The exit code of the command. `None` if it has been killed by a signal.
+    pub exit_code: Option<u32>,
+    /// The combined stdout and stderr of the command.
+    pub output: String,
+    /// The error, if the command couldn't be executed at all.
+    pub error: Option<String>,
+}
+
+impl ExecResult {
+    pub fn success(&self) -> bool {
+        self.error.is_none() && self.exit_code == Some(0)
+    }
+}
+
+/// Run an arbitrary command in all repositories that pass the filters.
+///
+/// If the filter contains findings, all repositories are checked first and the command is only
+/// run in those that have at least one of the given findings.
+pub fn exec(
+    state: &State,
+    config: &GeilConfig,
+    args: &ExecArgs,
+    filter: &RepositoryFilter,
+) -> Result<()> {
+    let repo_infos = state.repo_infos_by_wall_time(config, filter);
+    let parallel = !args.not_parallel;
+    let timeouts = config
+        .timeouts
+        .with_overrides(args.command_timeout, args.repository_timeout);
+
+    // Stop all running commands on Ctrl-C, but still show the results so far.
+    handle_interrupts()?;
+
+    // Prompts would be hidden by the progress bars of parallel runs.
+    let interactive = !parallel;
+    set_interactive(interactive);
+    let envs = command_environment(interactive);
+    let git = backend(config.backend, environment(interactive))?;
+
+    let (multi_progress, main_bar) = multi_progress_bar(repo_infos.len() as u64)?;
+
+    let results: Vec<Option<ExecResult>> = if parallel {
+        // Set the amount of threads, if specified.
+        if let Some(threads) = args.threads {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build_global()
+                .unwrap();
+        }
+
+        let results = repo_infos
+            .into_par_iter()
+            .map(|mut repo_info| {
+                repo_info.start_timeouts(&timeouts);
+                let result = exec_repo(
+                    &multi_progress,
+                    repo_info,
+                    args,
+                    &envs,
+                    git.as_ref(),
+                    filter,
+                );
+
+                main_bar.inc(1);
+                result
+            })
+            .collect();
+
+        main_bar.finish_with_message("All done: ");
+
+        results
+    } else {
+        let mut results = Vec::new();
+        for mut repo_info in repo_infos.into_iter() {
+            let start = Instant::now();
+            repo_info.start_timeouts(&timeouts);
+            let result = exec_repo(
+                &multi_progress,
+                repo_info,
+                args,
+                &envs,
+                git.as_ref(),
+                filter,
+            );
+
+            debug!("Command took {}ms", start.elapsed().as_millis());
+            main_bar.inc(1);
+            results.push(result);
+        }
+
+        results
+    };
+
+    // Finish and clean up the progress bar
+    main_bar.finish();
+    let _ = multi_progress.clear();
+
+    // Skip all repositories that have been filtered by their findings.
+    let mut results: Vec<ExecResult> = results.into_iter().flatten().collect();
+    results.sort_by(|a, b| a.path.cmp(&b.path));
+
+    if results.is_empty() {
+        println!("No repositories matched the given filters.");
+        return Ok(());
+    }
+
+    // The output has already been printed, if it has been streamed.
+    if !args.stream {
+        for result in results.iter().filter(|result| !result.output.is_empty()) {
+            println!("{}:", result.path.to_string_lossy());
+            println!("{}", result.output.trim_end());
+            println!();
+        }
+    }
+
+    let failed = results.iter().filter(|result| !result.success()).count();
+    print_exec_results(&results)?;
+
+    if interrupted() {
+        bail!("The run has been interrupted, the results are incomplete");
+    }
+
+    if failed > 0 {
+        bail!("Command failed in {failed} repositories");
+    }
+
+    Ok(())
+}
+
+/// Prepare the command that's run in each repository.
+///
+/// The arguments are passed to the program as they are, so their quoting is kept.
+/// With `shell`, they're joined to a command line, which is interpreted by the shell,
+/// e.g. `geil exec --shell 'cargo build && cargo test'`.
+pub fn exec_command(command: &[String], shell: bool) -> Cmd {
+    match command {
+        _ if shell => Cmd::shell(command.join(" ")),
+        [program, args @ ..] => args.iter().fold(Cmd::new(program), Cmd::arg),
+        [] => Cmd::shell(""),
+    }
+}
+
+/// Run the command in a single repository, including progress bar handling.
+///
+/// Returns `None`, if the repository doesn't have any of the findings requested by the filter.
+pub fn exec_repo(
+    multi_progress: &MultiProgress,
+    mut repo_info: RepositoryInfo,
+    args: &ExecArgs,
+    envs: &HashMap<String, String>,
+    git: &dyn GitBackend,
+    filter: &RepositoryFilter,
+) -> Option<ExecResult> {
+    let mut bar = ProgressBar::new(2);
+    let spinner_style =
+        ProgressStyle::with_template("{duration} {spinner} {prefix:.bold.white.dim} - {wide_msg}")
+            .unwrap()
+            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ");
+    bar.set_style(spinner_style);
+
+    // Add the bar to the end of the multi_bar.
+    bar = multi_progress.add(bar);
+
+    // Enable a steady tick after adding it to the bar, to ensure correct position rendering.
+    bar.enable_steady_tick(Duration::from_millis(125));
+
+    let name = repo_info.name.clone();
+    let mut result = None;
+
+    // Check the repository first, if we need to filter by findings.
+    if filter.needs_findings()
+        && let Err(err) = check_repo_inner(&bar, &mut repo_info, git, false)
+    {
+        repo_info.set_error(err);
+    }
+
+    if filter.matches_findings(&repo_info) {
+        bar.set_prefix(name.clone());
+        bar.set_message(format!("{name}: Running command"));
+
+        let cmd = exec_command(&args.command, args.shell)
+            .cwd(repo_info.path.clone())
+            .env(repository_environment(envs, &repo_info.path))
+            .timeout(repo_info.timeout());
+        let capture = if args.stream {
+            cmd.stream(|line| {
+                // Temporarily hide the progress bars, so they don't interfere with the output.
+                multi_progress.suspend(|| println!("[{name}] {line}"));
+            })
+        } else {
+            cmd.run()
+        };
+
+        result = Some(match capture {
+            Ok(capture) => ExecResult {
+                path: repo_info.path,
+                exit_code: capture.exit_status.code(),
+                output: String::from_utf8_lossy(&capture.stdout).into_owned(),
+                error: None,
+            },
+            Err(err) => ExecResult {
+                path: repo_info.path,
+                exit_code: None,
+                output: String::new(),
+                error: Some(format!("{err:#}")),
+            },
+        });
+    }
+
+    // Clean up this repo's progress bar.
+    bar.disable_steady_tick();
+    bar.finish();
+    multi_progress.remove(&bar);
+
+    result
+}
@@ -0,0 +1,99 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use anyhow::{Context, Result, bail};
+use log::error;
+
+use crate::{
+    config::GeilConfig,
+    filter::RepositoryFilter,
+    git::{default_branch, get_remotes},
+    manifest::{Manifest, ManifestEntry, contract_home},
+    repository_info::RepositoryInfo,
+    state::State,
+};
+
+/// Write a manifest of all known repositories, which can be used to clone them on another machine.
+/// The manifest is printed to stdout, if no path is given.
+pub fn export(
+    state: &State,
+    config: &GeilConfig,
+    path: Option<PathBuf>,
+    filter: &RepositoryFilter,
+) -> Result<()> {
+    let watched_paths = state.watched_paths(config);
+    let envs = HashMap::new();
+
+    let mut manifest = Manifest::default();
+    let mut failed = 0;
+    for repo_info in state.repo_infos_by_wall_time(config, filter) {
+        match manifest_entry(&repo_info, &envs, &watched_paths) {
+            Ok(entry) => manifest.repositories.push(entry),
+            Err(err) => {
+                error!("Failed to export repository {:?}: {err:#}", repo_info.path);
+                failed += 1;
+            }
+        }
+    }
+    manifest
+        .repositories
+        .sort_by(|a, b| (&a.watched, &a.path).cmp(&(&b.watched, &b.path)));
+
+    match path {
+        Some(path) => {
+            manifest.save(&path)?;
+            eprintln!(
+                "Exported {} repositories to {path:?}",
+                manifest.repositories.len()
+            );
+        }
+        None => print!(
+            "{}",
+            serde_yaml::to_string(&manifest).context("Failed to serialize manifest")?
+        ),
+    }
+
+    if failed > 0 {
+        bail!("Failed to export {failed} repositories");
+    }
+
+    Ok(())
+}
+
+/// Describe a single repository for the manifest.
+fn manifest_entry(
+    repo_info: &RepositoryInfo,
+    envs: &HashMap<String, String>,
+    watched_paths: &[PathBuf],
+) -> Result<ManifestEntry> {
+    let remotes = get_remotes(repo_info, envs)?;
+    // Prefer the `origin` remote, like `git clone` does.
+    let remote = remotes
+        .keys()
+        .find(|remote| *remote == "origin")
+        .or_else(|| remotes.keys().next());
+    let default_branch = match remote {
+        Some(remote) => default_branch(repo_info, envs, remote)?,
+        None => None,
+    };
+
+    // Store the path relative to the most specific watched directory it lives in.
+    let watched = watched_paths
+        .iter()
+        .filter(|watched| repo_info.path.starts_with(watched))
+        .max_by_key(|watched| watched.components().count());
+    let (watched, path) = match watched {
+        Some(watched) => (
+            Some(contract_home(watched)),
+            repo_info.path.strip_prefix(watched)?.to_path_buf(),
+        ),
+        None => (None, contract_home(&repo_info.path)),
+    };
+
+    Ok(ManifestEntry {
+        path,
+        watched,
+        remotes,
+        default_branch,
+        hook: repo_info.hook.clone(),
+    })
+}
@@ -3,7 +3,7 @@ use std::path::PathBuf;
 use anyhow::Result;
 use log::error;
 
-use crate::state::{State, discover};
+use crate::state::State;
 
 /// Explicitly ignore a given directory.
 /// No repositories will be updated or discovered, even if it's inside a watched directory.
@@ -26,16 +26,15 @@ pub fn ignore(state: &mut State, directories: &[PathBuf]) -> Result<()> {
             continue;
         }
 
-        // Scan the watched path for repositories, so we can forget about them
-        let mut repos = Vec::new();
-        discover(&state.ignored, &real_path, 0, &mut repos);
+        // Forget about all repositories inside of that directory.
+        state.repositories.retain(|repo| {
+            if !repo.path.starts_with(&real_path) {
+                return true;
+            }
 
-        for repo_to_remove in repos {
-            println!("Forgetting about repository: {:?}", repo_to_remove.path);
-            state
-                .repositories
-                .retain(|repo| repo.path != repo_to_remove.path);
-        }
+            println!("Forgetting about repository: {:?}", repo.path);
+            false
+        });
 
         println!("Ignoring directory: {real_path:?}");
         state.ignored.push(real_path.to_owned())
@@ -1,11 +1,11 @@
 use anyhow::Result;
 
-use crate::{config::GeilConfig, state::State};
+use crate::{config::GeilConfig, filter::RepositoryFilter, state::State};
 
-pub fn print_info(config: &GeilConfig, state: &State) -> Result<()> {
-    if !config.watched.is_empty() {
+pub fn print_info(config: &GeilConfig, state: &State, filter: &RepositoryFilter) -> Result<()> {
+    if !config.watched.is_empty() || !state.watched.is_empty() {
         println!("Watched folders:");
-        for watched in config.watched() {
+        for watched in state.watched_paths(config) {
             println!("  - {watched:?}");
         }
         println!();
@@ -19,18 +19,40 @@ pub fn print_info(config: &GeilConfig, state: &State) -> Result<()> {
         println!();
     }
 
-    if !config.keys.is_empty() {
+    let keys = state.keys(config);
+    if !keys.is_empty() {
         println!("Known keys:");
-        for key in &config.keys {
+        for key in &keys {
             println!("  - {} ({:?})", key.name, key.path());
         }
         println!();
     }
 
-    if !state.repositories.is_empty() {
+    if !config.groups.is_empty() {
+        println!("Groups:");
+        for group in &config.groups {
+            println!("  - {}", group.name);
+            for path in group.paths() {
+                println!("    - {path:?}");
+            }
+        }
+        println!();
+    }
+
+    let repositories: Vec<_> = state
+        .repositories
+        .iter()
+        .filter(|repo| filter.matches(&repo.path, &config.groups_of(&repo.path)))
+        .collect();
+    if !repositories.is_empty() {
         println!("Known repositories:\n");
-        for repo in &state.repositories {
-            println!("  - {:?}", repo.path);
+        for repo in repositories {
+            let groups = config.groups_of(&repo.path);
+            if groups.is_empty() {
+                println!("  - {:?}", repo.path);
+            } else {
+                println!("  - {:?} ({})", repo.path, groups.join(", "));
+            }
         }
     }
 
@@ -0,0 +1,95 @@
+use std::path::PathBuf;
+
+use anyhow::{Result, bail};
+use log::error;
+
+use crate::{
+    config::{GeilConfig, SshKey},
+    display::print_keys,
+    ssh_key::{fingerprint, loaded_keys, validate_key_pair},
+    state::State,
+};
+
+/// Register a new ssh key, which will be loaded into ssh-agent before updating.
+/// The key is stored in the state, the config file is never rewritten.
+pub fn add_key(
+    state: &mut State,
+    config: &GeilConfig,
+    path: PathBuf,
+    name: Option<String>,
+    optional: bool,
+) -> Result<()> {
+    // Store the absolute path.
+    let real_path = match std::fs::canonicalize(&path) {
+        Ok(real_path) => real_path,
+        Err(_) => bail!("Cannot find key at {path:?}"),
+    };
+    validate_key_pair(&real_path)?;
+
+    // Default to the file name of the key.
+    let name = match name {
+        Some(name) => name,
+        None => real_path
+            .file_name()
+            .map_or("no_name?".to_string(), |name| {
+                name.to_string_lossy().to_string()
+            }),
+    };
+
+    let keys = state.keys(config);
+    if keys.iter().any(|key| key.name == name) {
+        bail!("There's already a key with the name '{name}'");
+    }
+    if keys.iter().any(|key| key.path() == real_path) {
+        bail!("The key at {real_path:?} has already been added");
+    }
+
+    println!("Added key '{name}': {real_path:?}");
+    state.keys.push(SshKey::new(name, real_path, optional));
+
+    state.save()
+}
+
+/// Forget about one or more keys.
+/// Keys from the config file have to be removed there.
+pub fn remove_keys(state: &mut State, config: &GeilConfig, names: Vec<String>) -> Result<()> {
+    for name in names {
+        if !state.keys.iter().any(|key| key.name == name) {
+            if config.keys.iter().any(|key| key.name == name) {
+                error!("The key '{name}' is defined in the config file, remove it there");
+            } else {
+                error!("There's no key with the name '{name}'");
+            }
+            continue;
+        }
+
+        println!("Removed key: {name}");
+        state.keys.retain(|key| key.name != name);
+    }
+
+    state.save()
+}
+
+/// Print all known keys with their fingerprint and whether they're currently loaded.
+pub fn list_keys(state: &State, config: &GeilConfig) -> Result<()> {
+    let all_keys = state.keys(config);
+    if all_keys.is_empty() {
+        println!("No keys have been added yet.");
+        return Ok(());
+    }
+
+    let known_keys = loaded_keys()?;
+
+    let mut keys = Vec::new();
+    for key in all_keys.iter() {
+        // Keys might not exist on this machine, which is fine for optional keys.
+        let fingerprint = fingerprint(&key.path()).ok();
+        let loaded = fingerprint
+            .as_ref()
+            .is_some_and(|fingerprint| known_keys.contains(fingerprint));
+
+        keys.push((key, fingerprint, loaded));
+    }
+
+    print_keys(keys)
+}
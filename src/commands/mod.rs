@@ -1,13 +1,27 @@
 mod add;
 mod check;
+mod clone;
+mod exec;
+mod export;
 mod ignore;
 mod info;
+mod keys;
+mod prune;
 mod remove;
+mod scan;
 mod update;
+mod watch;
 
 pub use add::*;
 pub use check::*;
+pub use clone::*;
+pub use exec::*;
+pub use export::*;
 pub use ignore::*;
 pub use info::*;
+pub use keys::*;
+pub use prune::*;
 pub use remove::*;
+pub use scan::*;
 pub use update::*;
+pub use watch::*;
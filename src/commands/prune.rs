@@ -0,0 +1,226 @@
+use std::{
+    io::{Write, stdin, stdout},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, bail};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use log::info;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+use crate::{
+    config::GeilConfig,
+    display::{multi_progress_bar, print_prunable_branches},
+    filter::RepositoryFilter,
+    git::{
+        GitBackend, PrunableBranch, PruneReason, backend, environment, fetch, prunable_branches,
+    },
+    process::{handle_interrupts, interrupted, set_interactive},
+    repository_info::RepositoryInfo,
+    state::State,
+};
+
+/// Delete all local branches whose upstream is gone or that're merged into the default branch.
+///
+/// The branches are listed first and only deleted after confirmation, unless `yes` is set.
+/// Branches with commits that aren't part of the default branch are only deleted with `force`.
+pub fn prune(
+    state: &State,
+    config: &GeilConfig,
+    filter: &RepositoryFilter,
+    dry_run: bool,
+    yes: bool,
+    force: bool,
+    fetch: bool,
+) -> Result<()> {
+    let repo_infos = state.repo_infos_by_wall_time(config, filter);
+
+    // Stop all running git processes on Ctrl-C, but still show the results so far.
+    handle_interrupts()?;
+
+    // Prompts would be hidden by the progress bars.
+    set_interactive(false);
+    let git = backend(config.backend, environment(false))?;
+
+    let (multi_progress, main_bar) = multi_progress_bar(repo_infos.len() as u64)?;
+
+    let mut results: Vec<(RepositoryInfo, Vec<PrunableBranch>)> = repo_infos
+        .into_par_iter()
+        .map(|mut repo_info| {
+            repo_info.start_timeouts(&config.timeouts);
+            repo_info.prune = true;
+            let branches = find_branches(&multi_progress, &mut repo_info, git.as_ref(), fetch);
+
+            main_bar.inc(1);
+            (repo_info, branches)
+        })
+        .collect();
+
+    // Finish and clean up the progress bar
+    main_bar.finish();
+    let _ = multi_progress.clear();
+
+    results.sort_by(|(a, _), (b, _)| a.path.cmp(&b.path));
+    let failed = results
+        .iter()
+        .filter(|(repo_info, _)| repo_info.error.is_some())
+        .count();
+    let mut count: usize = results.iter().map(|(_, branches)| branches.len()).sum();
+
+    if count == 0 && failed == 0 {
+        println!("There're no branches to prune.");
+        return Ok(());
+    }
+    print_prunable_branches(&results)?;
+
+    if interrupted() {
+        bail!("The run has been interrupted, the results are incomplete");
+    }
+
+    // The commits of unmerged branches would be lost, so they're only deleted on request.
+    if !force {
+        for (_, branches) in results.iter_mut() {
+            branches.retain(|branch| branch.reason != PruneReason::Unmerged);
+        }
+        let remaining: usize = results.iter().map(|(_, branches)| branches.len()).sum();
+        if remaining < count {
+            println!(
+                "Skipping {} branches with unmerged commits. Pass --force to delete them as well.",
+                count - remaining
+            );
+        }
+        count = remaining;
+    }
+
+    if count > 0 && !dry_run && (yes || confirm(count)?) {
+        delete_branches(config, &mut results, git.as_ref())?;
+    }
+
+    if failed > 0 {
+        bail!("Failed to check {failed} repositories");
+    }
+
+    Ok(())
+}
+
+/// Fetch the repository and look for branches that can be deleted, including progress bar
+/// handling.
+///
+/// Any error is recorded on the [RepositoryInfo] and no branches are returned.
+fn find_branches(
+    multi_progress: &MultiProgress,
+    repo_info: &mut RepositoryInfo,
+    git: &dyn GitBackend,
+    fetch_first: bool,
+) -> Vec<PrunableBranch> {
+    let mut bar = ProgressBar::new(2);
+    let spinner_style =
+        ProgressStyle::with_template("{duration} {spinner} {prefix:.bold.white.dim} - {wide_msg}")
+            .unwrap()
+            .tick_chars("⠁⠂⠄⡀⢀⠠⠐⠈ ");
+    bar.set_style(spinner_style);
+
+    // Add the bar to the end of the multi_bar.
+    bar = multi_progress.add(bar);
+
+    // Enable a steady tick after adding it to the bar, to ensure correct position rendering.
+    bar.enable_steady_tick(Duration::from_millis(125));
+
+    let name = repo_info.name.clone();
+    let result = (|| {
+        if fetch_first {
+            bar.set_prefix(format!("[1/2] - {name}"));
+            bar.set_message(format!("{name}: Fetch from remote"));
+            fetch(repo_info, git)?;
+        }
+
+        bar.set_prefix(format!("[2/2] - {name}"));
+        bar.set_message(format!("{name}: Look for prunable branches"));
+        prunable_branches(repo_info, git)
+    })();
+
+    // Clean up this repo's progress bar.
+    bar.disable_steady_tick();
+    bar.finish();
+    multi_progress.remove(&bar);
+
+    match result {
+        Ok(branches) => branches,
+        Err(err) => {
+            repo_info.set_error(err);
+            Vec::new()
+        }
+    }
+}
+
+/// Ask the user whether the branches should really be deleted.
+fn confirm(count: usize) -> Result<bool> {
+    print!("Delete {count} branches? [y/N] ");
+    stdout().flush()?;
+
+    let mut answer = String::new();
+    stdin()
+        .read_line(&mut answer)
+        .context("Failed to read answer")?;
+
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Delete all found branches, one repository after another.
+/// Branches that can no longer be pruned for the reason they've been found with are skipped.
+fn delete_branches(
+    config: &GeilConfig,
+    results: &mut [(RepositoryInfo, Vec<PrunableBranch>)],
+    git: &dyn GitBackend,
+) -> Result<()> {
+    let mut failed = 0;
+    for (repo_info, branches) in results.iter_mut() {
+        // Waiting for the confirmation shouldn't count towards the repository timeout.
+        repo_info.start_timeouts(&config.timeouts);
+
+        let path = repo_info.path.to_string_lossy().to_string();
+
+        // The branches might have changed while waiting for the confirmation, e.g. due to new
+        // commits. Check them again, so only branches that can still be pruned for the same
+        // reason are deleted.
+        let current = match prunable_branches(repo_info, git) {
+            Ok(current) => current,
+            Err(err) => {
+                eprintln!("Couldn't check the branches in {path}: {err:#}");
+                failed += branches.len();
+                continue;
+            }
+        };
+
+        for branch in branches.iter() {
+            let unchanged = current
+                .iter()
+                .any(|current| current.name == branch.name && current.reason == branch.reason);
+            if !unchanged {
+                eprintln!(
+                    "Skipping {} in {path}, it has changed since it has been checked",
+                    branch.name
+                );
+                failed += 1;
+                continue;
+            }
+
+            match git.delete_branch(repo_info, &branch.name) {
+                Ok(()) => {
+                    info!("{}: Deleted branch {}", repo_info.name, branch.name);
+                    println!("Deleted {} in {path}", branch.name);
+                }
+                Err(err) => {
+                    eprintln!("Couldn't delete {} in {path}: {err:#}", branch.name);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("Failed to delete {failed} branches");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,16 @@
+use anyhow::Result;
+
+use crate::{config::GeilConfig, state::State};
+
+/// Look for new repositories in all watched directories and forget removed ones.
+pub fn scan(state: &mut State, config: &GeilConfig) -> Result<()> {
+    state.scan(config)?;
+
+    println!(
+        "Knowing {} repositories in {} watched directories",
+        state.repositories.len(),
+        state.watched_directories(config).len()
+    );
+
+    Ok(())
+}
@@ -1,81 +1,90 @@
-use std::{
-    collections::HashMap,
-    env::vars,
-    time::{Duration, Instant},
-};
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
+use anyhow::{Result, bail};
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 use log::debug;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 
 use crate::{
+    cli::{RunArgs, UpdateArgs},
+    commands::count_failed,
     config::GeilConfig,
     display::{multi_progress_bar, print_status},
-    git::{check_local_changes, check_unpushed_commits, fetch, get_stashed_entries, merge},
-    process::Cmd,
-    repository_info::{RepositoryInfo, RepositoryState},
+    filter::RepositoryFilter,
+    git::{
+        GitBackend, backend, check_default_branch, check_local_changes, check_unpushed_commits,
+        environment, fetch, get_stashed_entries, pull, update_branches,
+    },
+    process::{Cmd, handle_interrupts, interrupted, set_interactive},
+    repository_info::{RepositoryInfo, UpdateOutcome},
     state::State,
 };
 
 pub fn update(
     state: &mut State,
     config: &GeilConfig,
-    show_all: bool,
-    parallel: bool,
-    threads: Option<usize>,
+    args: &RunArgs,
+    update_args: &UpdateArgs,
 ) -> Result<()> {
-    let repo_infos = state.repo_infos_by_wall_time(config);
-
-    // Save all environment variables for later injection into git
-    let mut envs = HashMap::new();
-    for (key, value) in vars() {
-        envs.insert(key, value);
+    let repositories = state.repositories.iter().map(|repo| repo.path.as_path());
+    let filter = RepositoryFilter::new(config, &args.filter)?.paths(&args.paths, repositories)?;
+    let mut repo_infos = state.repo_infos_by_wall_time(config, &filter);
+    for repo_info in repo_infos.iter_mut() {
+        repo_info.all_branches = update_args.all_branches || config.all_branches(&repo_info.path);
+        repo_info.switch_merged = update_args.switch_merged;
+        repo_info.prune = update_args.prune || config.update.prune;
+        repo_info.strategy = update_args
+            .strategy
+            .unwrap_or_else(|| config.strategy(&repo_info.path));
     }
+    let ignore_untracked = args.ignore_untracked;
+    let timeouts = config
+        .timeouts
+        .with_overrides(args.command_timeout, args.repository_timeout);
+
+    // Stop all running git processes on Ctrl-C, but still show the results so far.
+    handle_interrupts()?;
+
+    let interactive = args.interactive();
+    set_interactive(interactive);
+    let git = backend(config.backend, environment(interactive))?;
 
     let (multi_progress, main_bar) = multi_progress_bar(repo_infos.len() as u64)?;
 
-    let repo_infos = if parallel {
+    let repo_infos = if !args.not_parallel {
         // Set the amount of threads, if specified.
-        if let Some(threads) = threads {
+        if let Some(threads) = args.threads {
             rayon::ThreadPoolBuilder::new()
                 .num_threads(threads)
                 .build_global()
                 .unwrap();
         }
 
-        let results: Result<Vec<RepositoryInfo>> = repo_infos
+        let results: Vec<RepositoryInfo> = repo_infos
             .into_par_iter()
             .map(|mut repo_info| {
                 // Handle the repository and track execution time.
                 let start = Instant::now();
-                let repo_path = repo_info.path.clone();
-                repo_info = match update_repo(&multi_progress, repo_info, &envs)
-                    .context(format!("Error while updating repo: {repo_path:?}"))
-                {
-                    Ok(repo_info) => repo_info,
-                    Err(err) => {
-                        // Make sure the bar gets incremented even if we get an error.
-                        main_bar.inc(1);
-                        return Err(err);
-                    }
-                };
+                repo_info.start_timeouts(&timeouts);
+                repo_info = update_repo(&multi_progress, repo_info, git.as_ref(), ignore_untracked);
                 repo_info.check_time = Some(start.elapsed().as_millis() as usize);
 
                 main_bar.inc(1);
-                Ok(repo_info)
+                repo_info
             })
             .collect();
 
         main_bar.finish_with_message("All done: ");
 
-        results?
+        results
     } else {
         let mut results = Vec::new();
-        for repo_info in repo_infos.into_iter() {
+        for mut repo_info in repo_infos.into_iter() {
             // Handle the repository and track execution time.
             let start = Instant::now();
-            let mut repo_info = update_repo(&multi_progress, repo_info, &envs)?;
+            repo_info.start_timeouts(&timeouts);
+            let mut repo_info =
+                update_repo(&multi_progress, repo_info, git.as_ref(), ignore_untracked);
             repo_info.check_time = Some(start.elapsed().as_millis() as usize);
 
             debug!("Check took {}ms", start.elapsed().as_millis());
@@ -91,19 +100,32 @@ pub fn update(
 
     state.update_check_times(&repo_infos)?;
 
-    print_status(repo_infos, show_all)?;
+    let failed = count_failed(&repo_infos);
+    print_status(repo_infos, args.all, args.output)?;
+
+    if interrupted() {
+        bail!("The run has been interrupted, the results are incomplete");
+    }
+
+    if failed > 0 {
+        bail!("Failed to update {failed} repositories");
+    }
 
     Ok(())
 }
 
 /// This is a simple wrapper around the actual repo handling function
 /// for easier progress bar handling.
+///
+/// Any error is recorded on the [RepositoryInfo], so a single failing repository
+/// doesn't abort the whole run.
 pub fn update_repo(
     multi_bar: &MultiProgress,
-    repo_info: RepositoryInfo,
-    envs: &HashMap<String, String>,
-) -> Result<RepositoryInfo> {
-    let mut bar = ProgressBar::new(5);
+    mut repo_info: RepositoryInfo,
+    git: &dyn GitBackend,
+    ignore_untracked: bool,
+) -> RepositoryInfo {
+    let mut bar = ProgressBar::new(8);
     let spinner_style =
         ProgressStyle::with_template("{duration} {spinner} {prefix:.bold.white.dim} - {wide_msg}")
             .unwrap()
@@ -117,60 +139,76 @@ pub fn update_repo(
     bar.enable_steady_tick(Duration::from_millis(125));
 
     // Run the actual repo handling logic.
-    let result = update_repo_inner(&bar, repo_info, envs);
+    if let Err(err) = update_repo_inner(&bar, &mut repo_info, git, ignore_untracked) {
+        repo_info.set_error(err);
+    }
 
     // Clean up this repo's progress bar.
     bar.disable_steady_tick();
     bar.finish();
     multi_bar.remove(&bar);
 
-    result
+    repo_info
 }
 
 pub fn update_repo_inner(
     bar: &ProgressBar,
-    mut repo_info: RepositoryInfo,
-    envs: &HashMap<String, String>,
-) -> Result<RepositoryInfo> {
+    repo_info: &mut RepositoryInfo,
+    git: &dyn GitBackend,
+    ignore_untracked: bool,
+) -> Result<()> {
     let name = repo_info.name.clone();
 
-    bar.set_prefix(format!("[1/5] - {name}"));
+    bar.set_prefix(format!("[1/8] - {name}"));
     bar.set_message(format!("{name}: Checking stash"));
-    get_stashed_entries(&mut repo_info, envs)?;
+    get_stashed_entries(repo_info, git)?;
 
-    bar.set_prefix(format!("[2/5] - {name}"));
+    bar.set_prefix(format!("[2/8] - {name}"));
     bar.set_message(format!("{name}: Fetch from remote"));
-    fetch(&mut repo_info, envs)?;
+    fetch(repo_info, git)?;
 
-    bar.set_prefix(format!("[3/5] - {name}"));
+    bar.set_prefix(format!("[3/8] - {name}"));
     bar.set_message(format!("{name}: Check for local changes"));
-    check_local_changes(&mut repo_info, envs)?;
-
-    // Skip update
-    // We cannot merge with local changes anyway.
-    if matches!(repo_info.state, RepositoryState::LocalChanges) {
-        return Ok(repo_info);
+    check_local_changes(repo_info, git, ignore_untracked)?;
+
+    // Switch away from merged branches first, so the default branch gets updated right away.
+    bar.set_prefix(format!("[4/8] - {name}"));
+    bar.set_message(format!("{name}: Compare with default branch"));
+    let switch_merged = repo_info.switch_merged;
+    check_default_branch(repo_info, git, switch_merged)?;
+
+    bar.set_prefix(format!("[5/8] - {name}"));
+    bar.set_message(format!("{name}: Try to update"));
+    let strategy = repo_info.strategy;
+    pull(repo_info, git, strategy)?;
+
+    // Other branches can always be updated, as their working tree isn't touched.
+    if repo_info.all_branches {
+        bar.set_prefix(format!("[6/8] - {name}"));
+        bar.set_message(format!("{name}: Fast forward other branches"));
+        update_branches(repo_info, git)?;
     }
 
-    bar.set_prefix(format!("[4/5] - {name}"));
-    bar.set_message(format!("{name}: Try to fast forward"));
-    merge(&mut repo_info, envs)?;
-
-    if matches!(repo_info.state, RepositoryState::Updated) {
-        // The repository has been updated.
-        // Check if we should run any hooks
-        if let Some(hook) = &repo_info.hook {
-            bar.set_prefix(format!("[5/5] - {name}"));
-            bar.set_message(format!("{name}: Running post-update hook"));
-
-            Cmd::new(hook.clone()).cwd(repo_info.path.clone()).run()?;
-        }
-    } else if matches!(repo_info.state, RepositoryState::UpToDate) {
-        bar.set_prefix(format!("[5/5] - {name}"));
-        bar.set_message(format!("{name}: Check for unpushed commits"));
-        // Check for any unpushed commits or a detached head.
-        check_unpushed_commits(&mut repo_info, envs)?;
+    // The repository has been updated.
+    // Check if we should run any hooks
+    if matches!(
+        repo_info.outcome,
+        UpdateOutcome::Updated | UpdateOutcome::UpdatedWithStash
+    ) && let Some(hook) = &repo_info.hook
+    {
+        bar.set_prefix(format!("[7/8] - {name}"));
+        bar.set_message(format!("{name}: Running post-update hook"));
+
+        Cmd::shell(hook)
+            .cwd(repo_info.path.clone())
+            .timeout(repo_info.timeout())
+            .run()?;
     }
 
-    Ok(repo_info)
+    bar.set_prefix(format!("[8/8] - {name}"));
+    bar.set_message(format!("{name}: Check for unpushed commits"));
+    // Check for any unpushed commits or a detached head.
+    check_unpushed_commits(repo_info, git)?;
+
+    Ok(())
 }
@@ -0,0 +1,125 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use log::error;
+
+use crate::{
+    config::{GeilConfig, Symlinks, WatchedDirectory},
+    discovery::discover,
+    state::State,
+};
+
+/// Watch one or more directories for repositories.
+/// All repositories inside those directories are discovered right away.
+///
+/// The discovery settings override the ones from the config for these directories.
+pub fn watch(
+    state: &mut State,
+    config: &GeilConfig,
+    directories: &[PathBuf],
+    list: bool,
+    max_depth: Option<usize>,
+    symlinks: Option<Symlinks>,
+) -> Result<()> {
+    // Just print the watched directories, if no arguments have been supplied.
+    if list || directories.is_empty() {
+        println!("Watched directories:");
+        for watched in state.watched_paths(config) {
+            println!("  - {watched:?}");
+        }
+        return Ok(());
+    }
+
+    let ignored = state.ignored(config)?;
+
+    for path in directories.iter() {
+        // Check if the directory to watch actually exists
+        if !path.exists() || !path.is_dir() {
+            error!("Cannot find directory at {path:?}");
+            continue;
+        }
+
+        // Get the absolute path
+        let real_path = std::fs::canonicalize(path)?;
+
+        if state.watched_paths(config).contains(&real_path) {
+            error!("The folder is already watched: {:?}", &real_path);
+            continue;
+        }
+
+        let mut watched = WatchedDirectory::new(real_path.clone());
+        watched.max_depth = max_depth;
+        watched.symlinks = symlinks;
+
+        // Immediately scan the folder, so the user sees what's going to be watched.
+        let (repositories, cache) = discover(
+            std::slice::from_ref(&watched),
+            &config.discovery,
+            &ignored,
+            &state.discovery_cache,
+        )?;
+        state.discovery_cache.extend(cache);
+        for repo in state.unknown_repositories(repositories) {
+            println!("Found new repository: {:?}", repo.path);
+            state.repositories.push(repo);
+        }
+
+        println!("Watching directory: {real_path:?}");
+        state.watched.push(watched);
+    }
+
+    state.save()
+}
+
+/// Stop watching one or more directories.
+/// All repositories that were only known through those directories are forgotten.
+pub fn unwatch(state: &mut State, config: &GeilConfig, directories: &[PathBuf]) -> Result<()> {
+    for path in directories.iter() {
+        // Get the absolute path, if the directory still exists.
+        let real_path = if path.exists() {
+            std::fs::canonicalize(path)?
+        } else {
+            path.clone()
+        };
+
+        if !state
+            .watched
+            .iter()
+            .any(|watched| watched.path() == real_path)
+        {
+            if config.watched().any(|watched| watched == real_path) {
+                error!(
+                    "The folder is watched via the config file. Please remove it there: {real_path:?}"
+                );
+            } else {
+                error!("The folder isn't watched: {real_path:?}");
+            }
+            continue;
+        }
+
+        println!("Unwatching directory: {real_path:?}");
+        state.watched.retain(|watched| watched.path() != real_path);
+
+        // Forget all repositories in that folder, unless they're still known through
+        // another watched folder or have been explicitly added via `geil add` or the config
+        // file.
+        let watched_paths = state.watched_paths(config);
+        let explicit_repos: Vec<PathBuf> = config.repositories().collect();
+        state.repositories.retain(|repo| {
+            if !repo.path.starts_with(&real_path)
+                || repo.added
+                || explicit_repos.contains(&repo.path)
+                || watched_paths
+                    .iter()
+                    .any(|watched| repo.path.starts_with(watched))
+            {
+                return true;
+            }
+
+            println!("Forgetting about repository: {:?}", repo.path);
+            false
+        });
+    }
+
+    state.save()
+}
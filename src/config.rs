@@ -1,6 +1,11 @@
-use std::{fs::File, path::PathBuf};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use anyhow::{Context, Result, anyhow};
+use clap::ValueEnum;
 use serde::{Deserialize, Serialize};
 use serde_with::{DefaultOnError, serde_as};
 use shellexpand::tilde;
@@ -11,12 +16,20 @@ pub struct SshKey {
     pub name: String,
     /// The path to the private key.
     path: PathBuf,
-    /// Whether this key is expected to exist.
+    /// Whether this key may be missing on this machine, in which case it's skipped.
     #[serde(default = "true_value")]
     pub optional: bool,
 }
 
 impl SshKey {
+    pub fn new(name: String, path: PathBuf, optional: bool) -> SshKey {
+        SshKey {
+            name,
+            path,
+            optional,
+        }
+    }
+
     pub fn path(&self) -> PathBuf {
         PathBuf::from(tilde(&self.path.to_string_lossy()).into_owned())
     }
@@ -33,26 +46,303 @@ pub struct Hook {
 }
 
 impl Hook {
+    pub fn new(path: PathBuf, command: String) -> Hook {
+        Hook { path, command }
+    }
+
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(tilde(&self.path.to_string_lossy()).into_owned())
+    }
+}
+
+/// How symlinked directories are handled while discovering repositories.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Symlinks {
+    /// Descend into symlinked directories. Symlinks to a directory above them are skipped, so
+    /// symlink loops are no problem.
+    #[default]
+    Follow,
+    /// Ignore symlinked directories.
+    Skip,
+}
+
+/// The default settings for discovering repositories in watched directories.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Discovery {
+    /// How many directories deep repositories are searched for.
+    #[serde(default = "default_max_depth")]
+    pub max_depth: usize,
+    #[serde(default = "Default::default")]
+    pub symlinks: Symlinks,
+}
+
+impl Default for Discovery {
+    fn default() -> Self {
+        Discovery {
+            max_depth: default_max_depth(),
+            symlinks: Symlinks::default(),
+        }
+    }
+}
+
+fn default_max_depth() -> usize {
+    5
+}
+
+/// A directory, in which repositories are discovered automatically.
+///
+/// It's either written as a plain path or with its own discovery settings.
+/// Unset settings fall back to the global [Discovery] settings.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(from = "WatchedEntry", into = "WatchedEntry")]
+pub struct WatchedDirectory {
+    path: PathBuf,
+    pub max_depth: Option<usize>,
+    pub symlinks: Option<Symlinks>,
+}
+
+impl WatchedDirectory {
+    pub fn new(path: PathBuf) -> WatchedDirectory {
+        WatchedDirectory {
+            path,
+            max_depth: None,
+            symlinks: None,
+        }
+    }
+
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(tilde(&self.path.to_string_lossy()).into_owned())
+    }
+
+    pub fn max_depth(&self, discovery: &Discovery) -> usize {
+        self.max_depth.unwrap_or(discovery.max_depth)
+    }
+
+    pub fn symlinks(&self, discovery: &Discovery) -> Symlinks {
+        self.symlinks.unwrap_or(discovery.symlinks)
+    }
+}
+
+/// The on-disk representation of a [WatchedDirectory].
+/// Directories without any settings are written as plain paths.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(untagged)]
+enum WatchedEntry {
+    Path(PathBuf),
+    Directory {
+        path: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        max_depth: Option<usize>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        symlinks: Option<Symlinks>,
+    },
+}
+
+impl From<WatchedEntry> for WatchedDirectory {
+    fn from(entry: WatchedEntry) -> Self {
+        match entry {
+            WatchedEntry::Path(path) => WatchedDirectory::new(path),
+            WatchedEntry::Directory {
+                path,
+                max_depth,
+                symlinks,
+            } => WatchedDirectory {
+                path,
+                max_depth,
+                symlinks,
+            },
+        }
+    }
+}
+
+impl From<WatchedDirectory> for WatchedEntry {
+    fn from(watched: WatchedDirectory) -> Self {
+        if watched.max_depth.is_none() && watched.symlinks.is_none() {
+            return WatchedEntry::Path(watched.path);
+        }
+
+        WatchedEntry::Directory {
+            path: watched.path,
+            max_depth: watched.max_depth,
+            symlinks: watched.symlinks,
+        }
+    }
+}
+
+/// Settings that only apply to a single repository.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RepositorySettings {
+    path: PathBuf,
+    /// The remote to compare against and update from.
+    /// If not set, the configured upstream of the current branch is used.
+    #[serde(default = "Default::default")]
+    pub remote: Option<String>,
+    /// Fast-forward all local branches with an upstream, not only the checked out one.
+    /// Overrides the global update settings.
+    #[serde(default = "Default::default")]
+    pub all_branches: Option<bool>,
+    /// How the checked out branch is updated.
+    /// Overrides the global update settings.
+    #[serde(default = "Default::default")]
+    pub strategy: Option<Strategy>,
+}
+
+impl RepositorySettings {
     pub fn path(&self) -> PathBuf {
         PathBuf::from(tilde(&self.path.to_string_lossy()).into_owned())
     }
 }
 
+/// A named group of repositories, e.g. `work` or `dotfiles`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Group {
+    pub name: String,
+    /// Repositories or whole directories that belong to this group.
+    /// All repositories inside of a directory are part of the group.
+    paths: Vec<PathBuf>,
+}
+
+impl Group {
+    pub fn paths(&self) -> impl Iterator<Item = PathBuf> {
+        self.paths
+            .iter()
+            .map(|path| PathBuf::from(tilde(&path.to_string_lossy()).into_owned()))
+    }
+
+    /// Check whether the repository at the given path belongs to this group.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.paths().any(|group_path| path.starts_with(group_path))
+    }
+}
+
+/// Timeouts for handling repositories, in seconds.
+/// There's no timeout, if they aren't set.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Timeouts {
+    /// The maximum time a single command may take.
+    #[serde(default = "Default::default")]
+    pub command: Option<u64>,
+    /// The maximum time all commands for a single repository may take together.
+    #[serde(default = "Default::default")]
+    pub repository: Option<u64>,
+}
+
+impl Timeouts {
+    /// Override the configured timeouts, e.g. with values from the command line.
+    pub fn with_overrides(&self, command: Option<u64>, repository: Option<u64>) -> Timeouts {
+        Timeouts {
+            command: command.or(self.command),
+            repository: repository.or(self.repository),
+        }
+    }
+
+    pub fn command(&self) -> Option<Duration> {
+        self.command.map(Duration::from_secs)
+    }
+
+    pub fn repository(&self) -> Option<Duration> {
+        self.repository.map(Duration::from_secs)
+    }
+}
+
+/// How the checked out branch is updated from its upstream.
+///
+/// All strategies roll back to the previous state, if the update causes conflicts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    /// Only fast-forward. Repositories with local changes are skipped.
+    #[default]
+    FfOnly,
+    /// Rebase local commits onto the upstream. Repositories with local changes are skipped.
+    Rebase,
+    /// Rebase local commits onto the upstream. Local changes are stashed and restored.
+    RebaseAutostash,
+    /// Stash local changes, fast-forward and restore them.
+    StashPullPop,
+}
+
+/// How repositories are updated.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct Update {
+    /// How the checked out branch is updated.
+    #[serde(default = "Default::default")]
+    pub strategy: Strategy,
+    /// Fast-forward all local branches with an upstream, not only the checked out one.
+    /// Branches that aren't checked out are updated without touching the working tree.
+    #[serde(default = "Default::default")]
+    pub all_branches: bool,
+    /// Delete remote refs that no longer exist on the remote while fetching.
+    #[serde(default = "Default::default")]
+    pub prune: bool,
+}
+
+/// The implementation that's used for git operations.
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Run the `git` binary for every operation.
+    #[default]
+    Cli,
+    /// Use libgit2 inside of geil's own process.
+    /// Only available, if geil has been built with the `native-git` feature.
+    Native,
+}
+
 #[serde_as]
 #[derive(Deserialize, Serialize)]
 pub struct GeilConfig {
     /// All paths that're actively watched for new repositories
-    pub watched: Vec<PathBuf>,
+    pub watched: Vec<WatchedDirectory>,
     /// All paths that're explicitly ignored.
+    /// These can be gitignore-style globs, e.g. `**/node_modules`.
     #[serde(default = "Default::default")]
     pub ignored: Vec<PathBuf>,
+    /// The default settings for discovering repositories in watched directories.
+    #[serde(default = "Default::default")]
+    pub discovery: Discovery,
     #[serde_as(deserialize_as = "DefaultOnError")]
     pub repositories: Vec<PathBuf>,
+    /// Ssh keys that should be loaded before updating.
+    /// Keys added via `geil keys add` are stored in the state instead.
     #[serde(default = "Default::default")]
     pub keys: Vec<SshKey>,
 
+    /// Commands that're executed after a repository has been updated.
     #[serde(default = "Default::default")]
     pub hooks: Vec<Hook>,
+
+    /// Per-repository settings.
+    #[serde(default = "Default::default")]
+    pub settings: Vec<RepositorySettings>,
+
+    /// Groups of repositories, which can be used to filter runs.
+    #[serde(default = "Default::default")]
+    pub groups: Vec<Group>,
+
+    #[serde(default = "Default::default")]
+    pub timeouts: Timeouts,
+
+    /// How repositories are updated.
+    /// Some of these settings can be overridden for single repositories.
+    #[serde(default = "Default::default")]
+    pub update: Update,
+
+    /// How git operations are done.
+    #[serde(default = "Default::default")]
+    pub backend: Backend,
+
+    /// The file this config has been loaded from.
+    /// The default location is used, if it isn't set.
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Default for GeilConfig {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl GeilConfig {
@@ -60,9 +350,16 @@ impl GeilConfig {
         GeilConfig {
             ignored: Vec::new(),
             watched: Vec::new(),
+            discovery: Discovery::default(),
             repositories: Vec::new(),
             keys: Vec::new(),
             hooks: Vec::new(),
+            settings: Vec::new(),
+            groups: Vec::new(),
+            timeouts: Timeouts::default(),
+            update: Update::default(),
+            backend: Backend::default(),
+            path: None,
         }
     }
 
@@ -73,9 +370,37 @@ impl GeilConfig {
     }
 
     pub fn watched(&self) -> impl Iterator<Item = PathBuf> {
-        self.watched
+        self.watched.iter().map(WatchedDirectory::path)
+    }
+
+    /// Get the settings for the repository at the given path, if there're any.
+    pub fn settings(&self, path: &Path) -> Option<&RepositorySettings> {
+        self.settings
             .iter()
-            .map(|old_path| PathBuf::from(tilde(&old_path.to_string_lossy()).into_owned()))
+            .find(|settings| settings.path() == path)
+    }
+
+    /// Whether all local branches of the repository at the given path should be updated.
+    pub fn all_branches(&self, path: &Path) -> bool {
+        self.settings(path)
+            .and_then(|settings| settings.all_branches)
+            .unwrap_or(self.update.all_branches)
+    }
+
+    /// The strategy to update the repository at the given path with.
+    pub fn strategy(&self, path: &Path) -> Strategy {
+        self.settings(path)
+            .and_then(|settings| settings.strategy)
+            .unwrap_or(self.update.strategy)
+    }
+
+    /// Get the names of all groups the repository at the given path belongs to.
+    pub fn groups_of(&self, path: &Path) -> Vec<String> {
+        self.groups
+            .iter()
+            .filter(|group| group.contains(path))
+            .map(|group| group.name.clone())
+            .collect()
     }
 
     pub fn repositories(&self) -> impl Iterator<Item = PathBuf> {
@@ -86,29 +411,53 @@ impl GeilConfig {
 }
 
 impl GeilConfig {
-    /// Load an existing state from the disk or create an empty new one.
-    pub fn load() -> Result<GeilConfig> {
-        let path = config_path()?;
-        // Return default path if it doesn't exist yet
+    /// Write the config to the file it has been loaded from.
+    ///
+    /// This is only used to create the default config. Commands never rewrite the config, as
+    /// that would drop all comments and formatting of the user. Their changes are stored in
+    /// the [State](crate::state::State) instead.
+    fn save(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => default_config_path()?,
+        };
+        let file = File::create(&path).context(format!("Failed to create config file {path:?}"))?;
+
+        serde_yaml::to_writer(file, &self).context("Failed to write config to disk:")?;
+
+        Ok(())
+    }
+
+    /// Load an existing config from the disk or create a new default one.
+    /// If no path is given, the config is expected at the default location.
+    pub fn load(path: Option<PathBuf>) -> Result<GeilConfig> {
+        let path = match path {
+            Some(path) => path,
+            None => default_config_path()?,
+        };
+        // Write the default config, if it doesn't exist yet.
         if !path.exists() {
-            let default_config = GeilConfig::new();
-            let file = File::create(&path)?;
-            serde_yaml::to_writer(file, &default_config)
-                .context("Failed to write state to disk:")?;
+            let default_config = GeilConfig {
+                path: Some(path.clone()),
+                ..GeilConfig::new()
+            };
+            default_config.save()?;
 
-            println!("Default config file has been written to {path:?}");
+            // Stdout might be parsed by scripts, e.g. with `--output json`.
+            eprintln!("Default config file has been written to {path:?}");
 
             return Ok(default_config);
         }
 
-        let file = File::open(path)?;
-        let state = serde_yaml::from_reader(file)?;
+        let file = File::open(&path)?;
+        let mut config: GeilConfig = serde_yaml::from_reader(file)?;
+        config.path = Some(path);
 
-        Ok(state)
+        Ok(config)
     }
 }
 
-fn config_path() -> Result<PathBuf> {
+fn default_config_path() -> Result<PathBuf> {
     let config_dir = dirs::config_dir().ok_or_else(|| anyhow!("Couldn't resolve config dir"))?;
     Ok(config_dir.join("geil.yml"))
 }
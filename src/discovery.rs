@@ -0,0 +1,382 @@
+//! Discovery of repositories inside of watched directories.
+//!
+//! Directories are searched in parallel. The modification time of every searched directory is
+//! cached, so unchanged directories don't have to be read again on the next discovery.
+//! Their content is known from the cached entries of their subdirectories.
+use std::{
+    collections::BTreeMap,
+    fs::{metadata, read_dir},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use anyhow::{Context, Result};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder, escape};
+use log::debug;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{Discovery, Symlinks, WatchedDirectory},
+    state::Repository,
+};
+
+/// Directories that're skipped while discovering repositories.
+///
+/// Patterns are gitignore-style globs, which are matched against absolute paths.
+/// Relative patterns such as `node_modules` or `build/cache` match at any depth.
+/// Plain paths are matched literally, even if they contain glob characters such as `[` or `*`.
+pub struct Ignored {
+    globs: GlobSet,
+}
+
+impl Ignored {
+    pub fn new(
+        patterns: impl IntoIterator<Item = PathBuf>,
+        paths: impl IntoIterator<Item = PathBuf>,
+    ) -> Result<Ignored> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in patterns {
+            let pattern = pattern.to_string_lossy();
+            let pattern = pattern.trim_end_matches('/');
+            let pattern = if pattern.starts_with('/') || pattern.starts_with("**") {
+                pattern.to_string()
+            } else {
+                format!("**/{pattern}")
+            };
+            builder.add(glob(&pattern)?);
+        }
+
+        for path in paths {
+            let path = path.to_string_lossy();
+            builder.add(glob(&escape(path.trim_end_matches('/')))?);
+        }
+
+        Ok(Ignored {
+            globs: builder.build()?,
+        })
+    }
+
+    /// Check whether the directory itself is ignored.
+    pub fn matches(&self, path: &Path) -> bool {
+        self.globs.is_match(path)
+    }
+
+    /// Check whether the path is inside of an ignored directory.
+    pub fn contains(&self, path: &Path) -> bool {
+        path.ancestors().any(|ancestor| self.matches(ancestor))
+    }
+}
+
+/// Build a glob, whose wildcards don't match path separators.
+fn glob(pattern: &str) -> Result<Glob> {
+    GlobBuilder::new(pattern)
+        .literal_separator(true)
+        .build()
+        .context(format!("Invalid ignore pattern: {pattern}"))
+}
+
+/// All directories that have been found by previous discoveries.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct DirectoryCache {
+    directories: BTreeMap<PathBuf, CachedDirectory>,
+}
+
+impl DirectoryCache {
+    /// Add the directories of another cache, replacing already cached ones.
+    pub fn extend(&mut self, other: DirectoryCache) {
+        self.directories.extend(other.directories);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.directories.is_empty()
+    }
+
+    /// The cached subdirectories of a directory.
+    /// Paths are ordered by their components, so all entries inside of a directory come right
+    /// after it.
+    fn subdirectories<'a>(
+        &'a self,
+        path: &'a Path,
+    ) -> impl Iterator<Item = (&'a PathBuf, &'a CachedDirectory)> {
+        self.directories
+            .range(path.to_path_buf()..)
+            .take_while(move |(child, _)| child.starts_with(path))
+            .filter(move |(child, _)| child.parent() == Some(path))
+    }
+}
+
+/// A directory that has been found during discovery.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+struct CachedDirectory {
+    /// The modification time of the directory at the time it has been read.
+    /// It changes whenever an entry is added to or removed from the directory.
+    /// Directories that have only been seen in their parent, e.g. because they're ignored or
+    /// too deep, don't have one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    modified: Option<SystemTime>,
+    /// Whether the directory contains a `.git` entry.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    repository: bool,
+    /// Whether the directory is a symlink.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    symlink: bool,
+}
+
+/// The parts of a directory's content that're relevant for discovery.
+struct Directory {
+    modified: SystemTime,
+    /// Whether the directory contains a `.git` entry.
+    repository: bool,
+    /// The directories inside of this directory and whether they're symlinks.
+    subdirectories: Vec<(PathBuf, bool)>,
+}
+
+/// Discover all repositories inside of the watched directories.
+///
+/// Every watched directory is searched on its own with its own settings, so the result doesn't
+/// depend on the order in which they're searched. If watched directories overlap, a repository
+/// is discovered as long as any of them reaches it, e.g. because it has a higher `max_depth`.
+///
+/// Directories that didn't change since they've been cached aren't read again.
+/// Returns the discovered repositories and a new cache of all found directories.
+pub fn discover(
+    watched: &[WatchedDirectory],
+    discovery: &Discovery,
+    ignored: &Ignored,
+    cache: &DirectoryCache,
+) -> Result<(Vec<Repository>, DirectoryCache)> {
+    let search = Search {
+        ignored,
+        previous: cache,
+        cache: Mutex::new(BTreeMap::new()),
+    };
+
+    // Use a dedicated pool, as the global pool is configured by the commands themselves.
+    let pool = rayon::ThreadPoolBuilder::new()
+        .build()
+        .context("Failed to start discovery threads")?;
+    let repositories = pool.install(|| {
+        watched
+            .par_iter()
+            .flat_map(|watched| {
+                let options = Options {
+                    max_depth: watched.max_depth(discovery),
+                    symlinks: watched.symlinks(discovery),
+                };
+                search.discover(&options, &watched.path(), 0, &[])
+            })
+            .collect()
+    });
+
+    let cache = DirectoryCache {
+        directories: search.cache.into_inner().unwrap(),
+    };
+
+    Ok((deduplicate(repositories), cache))
+}
+
+/// Remove repositories that have been found multiple times, e.g. via symlinks or overlapping
+/// watched directories.
+///
+/// The real path of a repository is preferred, otherwise the shortest path wins. That way, the
+/// result doesn't depend on the order in which directories have been searched.
+fn deduplicate(repositories: Vec<Repository>) -> Vec<Repository> {
+    let mut unique: BTreeMap<PathBuf, Repository> = BTreeMap::new();
+    for repo in repositories {
+        let real_path = real_path(&repo.path);
+        let rank = |path: &Path| {
+            (
+                path != real_path,
+                path.components().count(),
+                path.to_owned(),
+            )
+        };
+        match unique.get(&real_path) {
+            Some(known) if rank(&known.path) <= rank(&repo.path) => (),
+            _ => {
+                unique.insert(real_path.clone(), repo);
+            }
+        }
+    }
+
+    unique.into_values().collect()
+}
+
+/// The canonical path of a directory, or the path itself, if it cannot be resolved.
+pub fn real_path(path: &Path) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+}
+
+/// The discovery settings of a single watched directory.
+struct Options {
+    max_depth: usize,
+    symlinks: Symlinks,
+}
+
+/// The state of a single discovery run, which is shared by all threads.
+struct Search<'a> {
+    ignored: &'a Ignored,
+    /// The cache of previous discoveries.
+    previous: &'a DirectoryCache,
+    /// All directories that have been found during this run.
+    cache: Mutex<BTreeMap<PathBuf, CachedDirectory>>,
+}
+
+impl Search<'_> {
+    /// Search the directory for repositories.
+    ///
+    /// Repositories are recorded with the path they've been reached by, so they keep the path of
+    /// their watched directory, even if there're symlinks in between. `ancestors` contains the
+    /// real paths of all directories above this one, which are only known if symlinks are
+    /// followed.
+    fn discover(
+        &self,
+        options: &Options,
+        path: &Path,
+        depth: usize,
+        ancestors: &[PathBuf],
+    ) -> Vec<Repository> {
+        if self.ignored.matches(path) {
+            debug!("Skipping ignored folder {path:?}");
+            return Vec::new();
+        }
+
+        // A symlink to one of the directories above this one would be followed forever.
+        let mut ancestors = ancestors.to_vec();
+        if options.symlinks == Symlinks::Follow {
+            let Ok(real_path) = std::fs::canonicalize(path) else {
+                return Vec::new();
+            };
+            if self.ignored.matches(&real_path) {
+                debug!("Skipping ignored folder {real_path:?}");
+                return Vec::new();
+            }
+            if ancestors.contains(&real_path) {
+                debug!("Skipping symlink loop at {path:?}");
+                return Vec::new();
+            }
+            ancestors.push(real_path);
+        }
+        let path = path.to_owned();
+
+        // Recursion stop.
+        // There's no need to read the whole directory, only the .git directory is of interest.
+        debug!("{depth} Looking at folder {path:?}");
+        if depth >= options.max_depth {
+            if path.join(".git").exists() {
+                debug!("Found .git folder");
+                return vec![Repository::new(path)];
+            }
+            debug!("Max depth reached");
+            return Vec::new();
+        }
+
+        let Some(directory) = self.read(&path) else {
+            return Vec::new();
+        };
+        self.remember(&path, &directory);
+
+        // If a .git directory exists, always stop searching.
+        if directory.repository {
+            debug!("Found .git folder");
+            return vec![Repository::new(path)];
+        }
+
+        // The current path is no repository, search its subdirectories.
+        directory
+            .subdirectories
+            .par_iter()
+            .filter(|(_, symlink)| !symlink || options.symlinks == Symlinks::Follow)
+            .flat_map(|(subdirectory, _)| {
+                self.discover(options, subdirectory, depth + 1, &ancestors)
+            })
+            .collect()
+    }
+
+    /// Get the content of a directory.
+    /// The cached content is used, if the directory didn't change since it has been cached.
+    fn read(&self, path: &Path) -> Option<Directory> {
+        // The modification time is taken before reading the directory, so changes that happen
+        // while reading it are picked up by the next discovery.
+        let modified = match metadata(path).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            Err(err) => {
+                debug!("Couldn't read directory at {path:?} with error: {err:?}");
+                return None;
+            }
+        };
+
+        if let Some(cached) = self.previous.directories.get(path)
+            && cached.modified == Some(modified)
+        {
+            debug!("Using cached content of {path:?}");
+            return Some(Directory {
+                modified,
+                repository: cached.repository,
+                subdirectories: self
+                    .previous
+                    .subdirectories(path)
+                    .map(|(subdirectory, cached)| (subdirectory.clone(), cached.symlink))
+                    .collect(),
+            });
+        }
+
+        let current_dir = match read_dir(path) {
+            Ok(current_dir) => current_dir,
+            Err(err) => {
+                debug!("Couldn't read directory at {path:?} with error: {err:?}");
+                return None;
+            }
+        };
+
+        let mut directory = Directory {
+            modified,
+            repository: false,
+            subdirectories: Vec::new(),
+        };
+        for entry_result in current_dir {
+            let entry = match entry_result {
+                Ok(entry) => entry,
+                Err(err) => {
+                    debug!("Couldn't read directory path {path:?} with error: {err:?}");
+                    continue;
+                }
+            };
+
+            // Subdirectories of repositories are never searched.
+            if entry.file_name() == ".git" {
+                directory.repository = true;
+                directory.subdirectories.clear();
+                break;
+            }
+
+            // This follows symlinks, so symlinked directories are found as well.
+            if entry.path().is_dir() {
+                let symlink = entry
+                    .file_type()
+                    .is_ok_and(|file_type| file_type.is_symlink());
+                directory.subdirectories.push((entry.path(), symlink));
+            }
+        }
+
+        Some(directory)
+    }
+
+    /// Add a directory that has been read and all of its subdirectories to the new cache.
+    ///
+    /// All subdirectories are added, even if they're never searched, e.g. because they're
+    /// ignored. That way, they're still known, if they should be searched in a later run.
+    fn remember(&self, path: &Path, directory: &Directory) {
+        let mut cache = self.cache.lock().unwrap();
+        let cached = cache.entry(path.to_owned()).or_default();
+        cached.modified = Some(directory.modified);
+        cached.repository = directory.repository;
+
+        for (subdirectory, symlink) in &directory.subdirectories {
+            cache.entry(subdirectory.clone()).or_default().symlink = *symlink;
+        }
+    }
+}
@@ -1,8 +1,19 @@
+use std::{collections::BTreeSet, path::Path};
+
 use anyhow::{Context, Result};
 use comfy_table::*;
 use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use serde::Serialize;
 
-use crate::repository_info::{RepositoryInfo, RepositoryState};
+use crate::{
+    cli::OutputFormat,
+    commands::ExecResult,
+    config::SshKey,
+    git::{PrunableBranch, PruneReason},
+    repository_info::{
+        BranchOutcome, BranchUpdate, FileChanges, Finding, RepositoryInfo, UpdateOutcome,
+    },
+};
 
 pub fn multi_progress_bar(length: u64) -> Result<(MultiProgress, ProgressBar)> {
     let multi_progress = MultiProgress::new();
@@ -27,12 +38,102 @@ pub fn multi_progress_bar(length: u64) -> Result<(MultiProgress, ProgressBar)> {
     Ok((multi_progress, main_bar))
 }
 
-pub fn print_status(mut repo_infos: Vec<RepositoryInfo>, show_all: bool) -> Result<()> {
+/// The machine-readable representation of a repository for `--output json` and `--output yaml`.
+///
+/// Scripts rely on this format, so its fields are chosen explicitly instead of serializing the
+/// internal [RepositoryInfo].
+#[derive(Serialize)]
+struct RepositoryOutput<'a> {
+    /// The path to the repository.
+    path: &'a Path,
+    /// The name of the repository's directory.
+    name: &'a str,
+    /// What has been done with the repository.
+    outcome: &'a UpdateOutcome,
+    /// Everything noteworthy about the repository.
+    findings: &'a BTreeSet<Finding>,
+    /// The error that occurred while handling the repository.
+    error: Option<&'a str>,
+    /// The amount of stash entries.
+    stashed: usize,
+    /// The changed files in the working tree.
+    changes: &'a FileChanges,
+    /// The amount of commits the current branch is ahead of its upstream.
+    /// `null` if the branch has no upstream.
+    ahead: Option<usize>,
+    /// The amount of commits the current branch is behind its upstream.
+    /// `null` if the branch has no upstream.
+    behind: Option<usize>,
+    /// The default branch of the remote, if it's known.
+    default_branch: Option<&'a str>,
+    /// The amount of commits the current branch is behind the default branch.
+    /// `null` if the default branch is checked out or unknown.
+    behind_default: Option<usize>,
+    /// The merged branch that was checked out before switching to the default branch.
+    switched_from: Option<&'a str>,
+    /// The outcomes of all other local branches, if they've been updated.
+    branches: &'a [BranchUpdate],
+    /// The groups the repository belongs to.
+    groups: &'a [String],
+}
+
+impl<'a> From<&'a RepositoryInfo> for RepositoryOutput<'a> {
+    fn from(info: &'a RepositoryInfo) -> Self {
+        RepositoryOutput {
+            path: &info.path,
+            name: &info.name,
+            outcome: &info.outcome,
+            findings: &info.findings,
+            error: info.error.as_deref(),
+            stashed: info.stashed,
+            changes: &info.changes,
+            ahead: info.ahead,
+            behind: info.behind,
+            default_branch: info.default_branch.as_deref(),
+            behind_default: info.behind_default,
+            switched_from: info.switched_from.as_deref(),
+            branches: &info.branches,
+            groups: &info.groups,
+        }
+    }
+}
+
+pub fn print_status(
+    mut repo_infos: Vec<RepositoryInfo>,
+    show_all: bool,
+    output: OutputFormat,
+) -> Result<()> {
+    // Machine-readable output always contains all repositories.
+    let repositories: Vec<RepositoryOutput> =
+        repo_infos.iter().map(RepositoryOutput::from).collect();
+    match output {
+        OutputFormat::Table => (),
+        OutputFormat::Json => {
+            let json = serde_json::to_string_pretty(&repositories)
+                .context("Failed to serialize repositories to json")?;
+            println!("{json}");
+            return Ok(());
+        }
+        OutputFormat::Yaml => {
+            let yaml = serde_yaml::to_string(&repositories)
+                .context("Failed to serialize repositories to yaml")?;
+            print!("{yaml}");
+            return Ok(());
+        }
+    }
+
     // Filter all repos that don't need attention.
     if !show_all {
         repo_infos.retain(|info| {
-            !matches!(info.state, RepositoryState::UpToDate | RepositoryState::Ok)
-                || info.stashed != 0
+            !info.findings.is_empty()
+                || !matches!(
+                    info.outcome,
+                    UpdateOutcome::UpToDate | UpdateOutcome::Checked
+                )
+                || info
+                    .branches
+                    .iter()
+                    .any(|branch| branch.outcome != BranchOutcome::UpToDate)
         });
     }
 
@@ -45,12 +146,122 @@ pub fn print_status(mut repo_infos: Vec<RepositoryInfo>, show_all: bool) -> Resu
     table.set_content_arrangement(ContentArrangement::Dynamic);
     table.load_preset(comfy_table::presets::UTF8_FULL);
 
-    table.set_header(vec!["Path", "State", "Stash size"]);
+    // Only show the other branches, if any of them have been handled.
+    let show_branches = repo_infos.iter().any(|info| !info.branches.is_empty());
+
+    let mut header = vec![
+        "Path",
+        "Outcome",
+        "Findings",
+        "Changes",
+        "Stash size",
+        "Ahead",
+        "Behind",
+    ];
+    if show_branches {
+        header.push("Other branches");
+    }
+    table.set_header(header);
     for info in repo_infos.iter() {
-        table.add_row(vec![
+        let mut row = vec![
             Cell::new(info.path.to_string_lossy().into_owned()),
-            format_state(&info.state),
+            format_outcome(&info.outcome),
+            format_findings(info),
+            format_changes(&info.changes),
             format_number(info.stashed),
+            format_commit_count(info.ahead),
+            format_commit_count(info.behind),
+        ];
+        if show_branches {
+            row.push(format_branches(info));
+        }
+        table.add_row(row);
+    }
+
+    println!("{table}");
+
+    Ok(())
+}
+
+/// Print a summary table of the exit codes of a command that ran in multiple repositories.
+pub fn print_exec_results(results: &[ExecResult]) -> Result<()> {
+    let mut table = Table::new();
+    table.set_content_arrangement(ContentArrangement::Dynamic);
+    table.load_preset(comfy_table::presets::UTF8_FULL);
+
+    table.set_header(vec!["Path", "Exit code"]);
+    for result in results.iter() {
+        let exit_code = match (&result.error, result.exit_code) {
+            (Some(error), _) => Cell::new(format!("Error: {error}")).fg(Color::Red),
+            (None, Some(0)) => Cell::new("0").fg(Color::Green),
+            (None, Some(code)) => Cell::new(code.to_string()).fg(Color::Red),
+            (None, None) => Cell::new("Killed").fg(Color::Red),
+        };
+
+        table.add_row(vec![
+            Cell::new(result.path.to_string_lossy().into_owned()),
+            exit_code,
+        ]);
+    }
+
+    println!("{table}");
+
+    Ok(())
+}
+
+/// Print a table of all branches that can be deleted and of repositories that couldn't be checked.
+pub fn print_prunable_branches(results: &[(RepositoryInfo, Vec<PrunableBranch>)]) -> Result<()> {
+    let mut table = Table::new();
+    table.set_content_arrangement(ContentArrangement::Dynamic);
+    table.load_preset(comfy_table::presets::UTF8_FULL);
+
+    table.set_header(vec!["Path", "Branch", "Reason"]);
+    for (repo_info, branches) in results.iter() {
+        let path = repo_info.path.to_string_lossy().into_owned();
+        if let Some(error) = &repo_info.error {
+            table.add_row(vec![
+                Cell::new(&path),
+                Cell::new(""),
+                Cell::new(format!("Error: {error}")).fg(Color::Red),
+            ]);
+        }
+
+        for branch in branches {
+            let reason = match branch.reason {
+                PruneReason::UpstreamGone => Cell::new("Upstream is gone").fg(Color::Yellow),
+                PruneReason::Merged => Cell::new("Merged into default branch").fg(Color::Green),
+                PruneReason::Unmerged => {
+                    Cell::new("Upstream is gone, but not merged").fg(Color::Red)
+                }
+            };
+            table.add_row(vec![Cell::new(&path), Cell::new(&branch.name), reason]);
+        }
+    }
+
+    println!("{table}");
+
+    Ok(())
+}
+
+/// Print a table of keys with their fingerprint and whether they're loaded in ssh-agent.
+pub fn print_keys(keys: Vec<(&SshKey, Option<String>, bool)>) -> Result<()> {
+    let mut table = Table::new();
+    table.set_content_arrangement(ContentArrangement::Dynamic);
+    table.load_preset(comfy_table::presets::UTF8_FULL);
+
+    table.set_header(vec!["Name", "Path", "Optional", "Fingerprint", "Loaded"]);
+    for (key, fingerprint, loaded) in keys {
+        let fingerprint = match fingerprint {
+            Some(fingerprint) => Cell::new(fingerprint),
+            None => Cell::new("Key not found").fg(Color::Red),
+        };
+
+        table.add_row(vec![
+            Cell::new(&key.name),
+            Cell::new(key.path().to_string_lossy().into_owned()),
+            Cell::new(key.optional),
+            fingerprint,
+            format_bool(loaded),
         ]);
     }
 
@@ -59,17 +270,118 @@ pub fn print_status(mut repo_infos: Vec<RepositoryInfo>, show_all: bool) -> Resu
     Ok(())
 }
 
-pub fn format_state(state: &RepositoryState) -> Cell {
-    match state {
-        RepositoryState::Unknown => Cell::new("Unknown").fg(Color::Red),
-        RepositoryState::Detached => Cell::new("Detached HEAD").fg(Color::Yellow),
-        RepositoryState::Ok => Cell::new("Ok").fg(Color::Green),
-        RepositoryState::Updated => Cell::new("Updated").fg(Color::Green),
-        RepositoryState::UpToDate => Cell::new("Up to date").fg(Color::DarkGreen),
-        RepositoryState::Fetched => Cell::new("Fetched").fg(Color::Yellow),
-        RepositoryState::NoFastForward => Cell::new("No fast forward").fg(Color::Red),
-        RepositoryState::LocalChanges => Cell::new("Local changes").fg(Color::Red),
-        RepositoryState::NotPushed => Cell::new("Unpushed commits").fg(Color::Yellow),
+pub fn format_outcome(outcome: &UpdateOutcome) -> Cell {
+    match outcome {
+        UpdateOutcome::Unknown => Cell::new("Unknown").fg(Color::Red),
+        UpdateOutcome::Checked => Cell::new("Checked").fg(Color::DarkGreen),
+        UpdateOutcome::Updated => Cell::new("Updated").fg(Color::Green),
+        UpdateOutcome::UpdatedWithStash => Cell::new("Updated (stashed)").fg(Color::Green),
+        UpdateOutcome::RolledBack => Cell::new("Rolled back").fg(Color::Red),
+        UpdateOutcome::UpToDate => Cell::new("Up to date").fg(Color::DarkGreen),
+        UpdateOutcome::Fetched => Cell::new("Fetched").fg(Color::Yellow),
+    }
+}
+
+/// Format all findings of a repository, one per line.
+/// The cell is colored by the most severe finding.
+pub fn format_findings(info: &RepositoryInfo) -> Cell {
+    if info.findings.is_empty() {
+        return Cell::new("Ok").fg(Color::Green);
+    }
+
+    let mut lines = Vec::new();
+    let mut color = Color::Yellow;
+    for finding in info.findings.iter() {
+        let (text, severe) = match finding {
+            Finding::LocalChanges => ("Local changes".to_string(), true),
+            Finding::Stashed => ("Stashed changes".to_string(), false),
+            Finding::NotPushed => ("Unpushed commits".to_string(), false),
+            Finding::Behind => ("Behind upstream".to_string(), false),
+            Finding::Detached => ("Detached HEAD".to_string(), false),
+            Finding::NoFastForward => ("No fast forward".to_string(), true),
+            Finding::NoUpstream => ("No upstream".to_string(), false),
+            Finding::UpdateConflict => ("Update conflict".to_string(), true),
+            Finding::NotOnDefaultBranch => (
+                format!(
+                    "Not on {} ({} behind)",
+                    info.default_branch.as_deref().unwrap_or("default branch"),
+                    info.behind_default.unwrap_or_default()
+                ),
+                false,
+            ),
+            Finding::Merged => ("Branch has been merged".to_string(), false),
+            Finding::Switched => (
+                format!(
+                    "Switched from {} to {}",
+                    info.switched_from.as_deref().unwrap_or_default(),
+                    info.default_branch.as_deref().unwrap_or_default()
+                ),
+                false,
+            ),
+            // Show the actual error message for failed repositories.
+            Finding::Error => (
+                format!("Error: {}", info.error.as_deref().unwrap_or_default()),
+                true,
+            ),
+            // The error messages already explain what went wrong.
+            Finding::TimedOut => (info.error.clone().unwrap_or_default(), true),
+            Finding::Interrupted => ("Interrupted".to_string(), true),
+            Finding::AuthenticationRequired => (info.error.clone().unwrap_or_default(), true),
+        };
+
+        if severe {
+            color = Color::Red;
+        }
+        lines.push(text);
+    }
+
+    Cell::new(lines.join("\n")).fg(color)
+}
+
+/// Format all other branches that have been updated or couldn't be updated, one per line.
+/// The cell is red, if any branch couldn't be fast-forwarded.
+pub fn format_branches(info: &RepositoryInfo) -> Cell {
+    let mut lines = Vec::new();
+    let mut color = Color::Green;
+    for branch in info.branches.iter() {
+        match branch.outcome {
+            BranchOutcome::UpToDate => continue,
+            BranchOutcome::Updated => lines.push(format!("{}: Updated", branch.name)),
+            BranchOutcome::NoFastForward => {
+                color = Color::Red;
+                lines.push(format!("{}: No fast forward", branch.name));
+            }
+        }
+    }
+
+    if lines.is_empty() {
+        return Cell::new("-").fg(Color::DarkGreen);
+    }
+
+    Cell::new(lines.join("\n")).fg(color)
+}
+
+/// Format the local changes as a short summary, e.g. `2 staged, 3 untracked`.
+pub fn format_changes(changes: &FileChanges) -> Cell {
+    let mut parts = Vec::new();
+    for (count, kind) in [
+        (changes.staged, "staged"),
+        (changes.modified, "modified"),
+        (changes.untracked, "untracked"),
+        (changes.conflicted, "conflicted"),
+    ] {
+        if count != 0 {
+            parts.push(format!("{count} {kind}"));
+        }
+    }
+
+    if parts.is_empty() {
+        Cell::new("-").fg(Color::Green)
+    } else if changes.is_clean(true) {
+        // Only untracked files are somewhat less critical.
+        Cell::new(parts.join(", ")).fg(Color::Yellow)
+    } else {
+        Cell::new(parts.join(", ")).fg(Color::Red)
     }
 }
 
@@ -79,3 +391,19 @@ pub fn format_number(number: usize) -> Cell {
         _ => Cell::new(number.to_string()).fg(Color::Red),
     }
 }
+
+/// Format an ahead/behind count. A missing count means that there's no upstream.
+pub fn format_commit_count(count: Option<usize>) -> Cell {
+    match count {
+        None => Cell::new("-"),
+        Some(0) => Cell::new("0").fg(Color::Green),
+        Some(count) => Cell::new(count.to_string()).fg(Color::Yellow),
+    }
+}
+
+pub fn format_bool(value: bool) -> Cell {
+    match value {
+        true => Cell::new("yes").fg(Color::Green),
+        false => Cell::new("no").fg(Color::Red),
+    }
+}
@@ -0,0 +1,168 @@
+//! Filters that restrict the set of repositories a command works on.
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, bail};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use regex::Regex;
+
+use crate::{
+    cli::FilterArgs,
+    config::GeilConfig,
+    repository_info::{Finding, RepositoryInfo},
+};
+
+/// Patterns with this prefix are interpreted as regular expressions instead of globs.
+const REGEX_PREFIX: &str = "re:";
+
+/// A filter for repositories.
+/// The default filter matches all repositories.
+#[derive(Default)]
+pub struct RepositoryFilter {
+    /// Repositories have to match at least one of these globs or regexes, if there're any.
+    globs: Option<GlobSet>,
+    regexes: Vec<Regex>,
+    /// Repositories have to be one of these, if there're any.
+    paths: Vec<PathBuf>,
+    /// Repositories have to be in at least one of these groups, if there're any.
+    groups: Vec<String>,
+    /// Repositories must not be in any of these groups.
+    excluded_groups: Vec<String>,
+    /// Repositories have to have at least one of these findings, if there're any.
+    /// Since findings are only known after checking a repository, this has to be
+    /// explicitly checked via [RepositoryFilter::matches_findings].
+    findings: Vec<Finding>,
+}
+
+impl RepositoryFilter {
+    /// Create a filter from the filter options that have been passed on the command line.
+    pub fn new(config: &GeilConfig, args: &FilterArgs) -> Result<RepositoryFilter> {
+        // Catch typos early, instead of silently matching nothing.
+        for name in args.group.iter().chain(args.exclude_group.iter()) {
+            if !config.groups.iter().any(|group| &group.name == name) {
+                bail!("Unknown group: {name}");
+            }
+        }
+
+        let mut globs = Vec::new();
+        let mut regexes = Vec::new();
+        for pattern in &args.filter {
+            match pattern.strip_prefix(REGEX_PREFIX) {
+                Some(regex) => regexes
+                    .push(Regex::new(regex).context(format!("Invalid regex pattern: {regex}"))?),
+                None => globs.push(pattern),
+            }
+        }
+
+        let globs = if globs.is_empty() {
+            None
+        } else {
+            let mut builder = GlobSetBuilder::new();
+            for glob in globs {
+                builder.add(Glob::new(glob).context(format!("Invalid glob pattern: {glob}"))?);
+            }
+            Some(builder.build()?)
+        };
+
+        Ok(RepositoryFilter {
+            globs,
+            regexes,
+            groups: args.group.clone(),
+            excluded_groups: args.exclude_group.clone(),
+            ..Default::default()
+        })
+    }
+
+    /// Only match repositories inside of the given paths.
+    /// If there's no repository inside of a path, the innermost repository containing that path
+    /// is matched instead. That way, the repository of the current directory can be selected.
+    pub fn paths<'a>(
+        mut self,
+        paths: &[PathBuf],
+        repositories: impl Iterator<Item = &'a Path>,
+    ) -> Result<RepositoryFilter> {
+        // Repositories found via symlinked directories keep the path they've been found at,
+        // so both sides are compared by their real path.
+        let repositories: Vec<(&Path, PathBuf)> = repositories
+            .map(|repo| {
+                (
+                    repo,
+                    std::fs::canonicalize(repo).unwrap_or(repo.to_path_buf()),
+                )
+            })
+            .collect();
+
+        for path in paths {
+            let path = std::fs::canonicalize(path).context(format!("Cannot find path {path:?}"))?;
+
+            let inside: Vec<&Path> = repositories
+                .iter()
+                .filter(|(_, real_path)| real_path.starts_with(&path))
+                .map(|(repo, _)| *repo)
+                .collect();
+            if !inside.is_empty() {
+                self.paths.extend(inside.into_iter().map(Path::to_path_buf));
+                continue;
+            }
+
+            let containing = repositories
+                .iter()
+                .filter(|(_, real_path)| path.starts_with(real_path))
+                .max_by_key(|(_, real_path)| real_path.components().count());
+            match containing {
+                Some((repo, _)) => self.paths.push(repo.to_path_buf()),
+                None => bail!("There's no known repository at {path:?}"),
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Only match repositories that have at least one of the given findings.
+    pub fn findings(mut self, findings: Vec<Finding>) -> RepositoryFilter {
+        self.findings = findings;
+        self
+    }
+
+    /// Check whether a repository at the given path, which belongs to the given groups,
+    /// passes this filter.
+    pub fn matches(&self, path: &Path, groups: &[String]) -> bool {
+        if (self.globs.is_some() || !self.regexes.is_empty()) && !self.matches_pattern(path) {
+            return false;
+        }
+
+        if !self.paths.is_empty() && !self.paths.iter().any(|filter| filter == path) {
+            return false;
+        }
+
+        if !self.groups.is_empty() && !groups.iter().any(|group| self.groups.contains(group)) {
+            return false;
+        }
+
+        !groups
+            .iter()
+            .any(|group| self.excluded_groups.contains(group))
+    }
+
+    /// Check whether the path matches any of the globs or regexes.
+    fn matches_pattern(&self, path: &Path) -> bool {
+        if let Some(globs) = &self.globs
+            && globs.is_match(path)
+        {
+            return true;
+        }
+
+        let path = path.to_string_lossy();
+        self.regexes.iter().any(|regex| regex.is_match(&path))
+    }
+
+    /// Whether repositories need to be checked before [RepositoryFilter::matches_findings]
+    /// can be used.
+    pub fn needs_findings(&self) -> bool {
+        !self.findings.is_empty()
+    }
+
+    /// Check whether an already checked repository has any of the requested findings.
+    pub fn matches_findings(&self, repo_info: &RepositoryInfo) -> bool {
+        self.findings.is_empty() || self.findings.iter().any(|finding| repo_info.has(*finding))
+    }
+}
@@ -0,0 +1,637 @@
+//! The default backend, which runs the `git` binary for every operation.
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail};
+use subprocess::Capture;
+
+use super::{
+    AuthenticationRequired, FastForward, GitBackend, is_authentication_failure,
+    repository_environment,
+};
+use crate::{
+    cmd,
+    manifest::ManifestEntry,
+    process::Cmd,
+    repository_info::{FileChanges, RepositoryInfo},
+};
+
+/// A [GitBackend] that runs `git` processes.
+///
+/// This behaves exactly like git on the command line, including all of the user's git and ssh
+/// configuration, but spawns several processes per repository.
+pub struct CliBackend {
+    envs: HashMap<String, String>,
+}
+
+impl CliBackend {
+    pub fn new(envs: HashMap<String, String>) -> CliBackend {
+        CliBackend { envs }
+    }
+
+    /// Check whether a commit is an ancestor of another one.
+    fn is_ancestor(
+        &self,
+        repo_info: &RepositoryInfo,
+        ancestor: &str,
+        commit: &str,
+    ) -> Result<bool> {
+        let capture_data = self
+            .git(repo_info)
+            .arg("merge-base")
+            .arg("--is-ancestor")
+            .arg(ancestor)
+            .arg(commit)
+            .run()?;
+
+        Ok(capture_data.success())
+    }
+
+    /// Count the commits that're only on the local ref and only on the upstream ref.
+    fn count_commits(
+        &self,
+        repo_info: &RepositoryInfo,
+        local: &str,
+        upstream: &str,
+    ) -> Result<(usize, usize)> {
+        let capture_data = self
+            .git(repo_info)
+            .arg("rev-list")
+            .arg("--left-right")
+            .arg("--count")
+            .arg(format!("{local}...{upstream}"))
+            .run()?;
+        ensure_success(&capture_data, &format!("Couldn't compare with {upstream}"))?;
+
+        // The output looks like this: `1\t2`
+        let stdout = String::from_utf8_lossy(&capture_data.stdout);
+        let mut counts = stdout.split_whitespace().map(|count| count.parse().ok());
+        match (counts.next().flatten(), counts.next().flatten()) {
+            (Some(ahead), Some(behind)) => Ok((ahead, behind)),
+            _ => bail!("Couldn't parse commit counts from output: {stdout}"),
+        }
+    }
+
+    /// Check out the commits of all submodules, which are recorded in the current commit.
+    fn update_submodules(&self, repo_info: &RepositoryInfo) -> Result<()> {
+        let capture_data = self
+            .remote_git(repo_info)
+            .arg("submodule")
+            .arg("update")
+            .arg("--init")
+            .arg("--recursive")
+            .run()?;
+        ensure_success(&capture_data, "Failed to update submodules")
+    }
+
+    /// Prepare a git command, which runs inside the repository.
+    fn git(&self, repo_info: &RepositoryInfo) -> Cmd {
+        cmd!("git")
+            .cwd(repo_info.path.clone())
+            .env(self.envs.clone())
+            .timeout(repo_info.timeout())
+    }
+
+    /// Prepare a git command, which runs inside the repository and might connect to a remote.
+    /// The repository's own ssh command is respected, see [repository_environment].
+    fn remote_git(&self, repo_info: &RepositoryInfo) -> Cmd {
+        self.git(repo_info)
+            .env(repository_environment(&self.envs, &repo_info.path))
+    }
+}
+
+impl GitBackend for CliBackend {
+    fn stash_count(&self, repo_info: &RepositoryInfo) -> Result<usize> {
+        // There are no stashes, if the stash ref doesn't exist.
+        if rev_parse(repo_info, &self.envs, "refs/stash")?.is_none() {
+            return Ok(0);
+        }
+
+        let capture_data = self
+            .git(repo_info)
+            .arg("rev-list")
+            .arg("--walk-reflogs")
+            .arg("--count")
+            .arg("refs/stash")
+            .run()?;
+        ensure_success(&capture_data, "Couldn't count stash entries")?;
+        let stdout = String::from_utf8_lossy(&capture_data.stdout);
+
+        stdout
+            .trim()
+            .parse::<usize>()
+            .context(format!("Couldn't get stash amount from output: {stdout}"))
+    }
+
+    fn status(&self, repo_info: &RepositoryInfo) -> Result<FileChanges> {
+        let capture_data = self
+            .git(repo_info)
+            .arg("status")
+            .arg("--porcelain=v2")
+            .run()?;
+        ensure_success(&capture_data, "Couldn't get repository status")?;
+        let stdout = String::from_utf8_lossy(&capture_data.stdout);
+
+        // Lines starting with `#` are headers, every other line is a changed file.
+        // Changed files look like this: `1 XY ...`, where `X` is the staged state
+        // and `Y` the state in the working tree. `.` means unchanged.
+        let mut changes = FileChanges::default();
+        for line in stdout.lines() {
+            let mut parts = line.split(' ');
+            match parts.next() {
+                // Ordinary changed entries and renamed/copied entries.
+                Some("1") | Some("2") => {
+                    let xy = parts.next().unwrap_or_default();
+                    if xy.chars().next().is_some_and(|x| x != '.') {
+                        changes.staged += 1;
+                    }
+                    if xy.chars().nth(1).is_some_and(|y| y != '.') {
+                        changes.modified += 1;
+                    }
+                }
+                Some("u") => changes.conflicted += 1,
+                Some("?") => changes.untracked += 1,
+                _ => continue,
+            }
+        }
+
+        Ok(changes)
+    }
+
+    fn fetch(&self, repo_info: &RepositoryInfo) -> Result<bool> {
+        // Remember the remote refs, so we can detect whether anything changed.
+        let refs_before = remote_refs(repo_info, &self.envs)?;
+
+        let fetch = self.remote_git(repo_info).arg("fetch").arg("--all");
+        let fetch = if repo_info.prune {
+            fetch.arg("--prune")
+        } else {
+            fetch
+        };
+        let capture_data = fetch.run()?;
+        ensure_success(&capture_data, "Failed to fetch from remote")?;
+
+        Ok(refs_before != remote_refs(repo_info, &self.envs)?)
+    }
+
+    fn current_branch(&self, repo_info: &RepositoryInfo) -> Result<Option<String>> {
+        let capture_data = self
+            .git(repo_info)
+            .arg("symbolic-ref")
+            .arg("--quiet")
+            .arg("--short")
+            .arg("HEAD")
+            .run()?;
+
+        if !capture_data.success() {
+            return Ok(None);
+        }
+
+        Ok(Some(
+            String::from_utf8_lossy(&capture_data.stdout)
+                .trim()
+                .to_string(),
+        ))
+    }
+
+    fn upstream(&self, repo_info: &RepositoryInfo, branch: &str) -> Result<Option<String>> {
+        // Use the remote branch with the same name on the configured remote.
+        if let Some(remote) = &repo_info.remote {
+            let upstream = format!("refs/remotes/{remote}/{branch}");
+            if rev_parse(repo_info, &self.envs, &upstream)?.is_none() {
+                return Ok(None);
+            }
+
+            return Ok(Some(upstream));
+        }
+
+        // Resolve the full ref name of the branch's configured upstream.
+        let capture_data = self
+            .git(repo_info)
+            .arg("rev-parse")
+            .arg("--symbolic-full-name")
+            .arg(format!("{branch}@{{upstream}}"))
+            .run()?;
+        if !capture_data.success() {
+            return Ok(None);
+        }
+        let upstream = String::from_utf8_lossy(&capture_data.stdout)
+            .trim()
+            .to_string();
+
+        Ok(Some(upstream))
+    }
+
+    fn fast_forward(&self, repo_info: &RepositoryInfo, upstream: &str) -> Result<FastForward> {
+        let head_before = rev_parse(repo_info, &self.envs, "HEAD")?;
+
+        let capture_data = self
+            .git(repo_info)
+            .arg("merge")
+            .arg("--ff-only")
+            .arg(upstream)
+            .run()?;
+
+        // The merge fails, if the branches diverged.
+        if !capture_data.success() {
+            return Ok(FastForward::NotPossible(
+                String::from_utf8_lossy(&capture_data.stdout)
+                    .trim()
+                    .to_string(),
+            ));
+        }
+
+        if head_before == rev_parse(repo_info, &self.envs, "HEAD")? {
+            return Ok(FastForward::UpToDate);
+        }
+
+        // Update any submodules if this worked out.
+        self.update_submodules(repo_info)?;
+
+        Ok(FastForward::Updated)
+    }
+
+    fn rebase(&self, repo_info: &RepositoryInfo, upstream: &str) -> Result<FastForward> {
+        let head_before = rev_parse(repo_info, &self.envs, "HEAD")?;
+
+        // Be explicit, so the user's `rebase.autoStash` setting doesn't matter.
+        // Local changes are stashed by geil itself, if the strategy allows it.
+        let capture_data = self
+            .git(repo_info)
+            .arg("rebase")
+            .arg("--no-autostash")
+            .arg(upstream)
+            .run()?;
+
+        if !capture_data.success() {
+            let output = String::from_utf8_lossy(&capture_data.stdout)
+                .trim()
+                .to_string();
+
+            // Restore the previous state.
+            // Aborting fails, if the rebase didn't even start, in which case nothing changed.
+            let abort = self.git(repo_info).arg("rebase").arg("--abort").run()?;
+            if !abort.success() && rebase_in_progress(repo_info, &self.envs)? {
+                ensure_success(&abort, "Failed to roll back the rebase")?;
+            }
+
+            return Ok(FastForward::NotPossible(output));
+        }
+
+        if head_before == rev_parse(repo_info, &self.envs, "HEAD")? {
+            return Ok(FastForward::UpToDate);
+        }
+
+        self.update_submodules(repo_info)?;
+
+        Ok(FastForward::Updated)
+    }
+
+    fn stash(&self, repo_info: &RepositoryInfo) -> Result<bool> {
+        let stash_before = rev_parse(repo_info, &self.envs, "refs/stash")?;
+
+        let capture_data = self
+            .git(repo_info)
+            .arg("stash")
+            .arg("push")
+            .arg("--message")
+            .arg("geil: Local changes during update")
+            .run()?;
+        ensure_success(&capture_data, "Failed to stash local changes")?;
+
+        // Nothing is stashed, if there aren't any changes to tracked files.
+        Ok(rev_parse(repo_info, &self.envs, "refs/stash")? != stash_before)
+    }
+
+    fn stash_pop(&self, repo_info: &RepositoryInfo) -> Result<bool> {
+        let capture_data = self.git(repo_info).arg("stash").arg("pop").run()?;
+
+        Ok(capture_data.success())
+    }
+
+    fn head_commit(&self, repo_info: &RepositoryInfo) -> Result<String> {
+        rev_parse(repo_info, &self.envs, "HEAD")?.context("HEAD doesn't point to a commit")
+    }
+
+    fn reset_hard(&self, repo_info: &RepositoryInfo, commit: &str) -> Result<()> {
+        let capture_data = self
+            .git(repo_info)
+            .arg("reset")
+            .arg("--hard")
+            .arg("--quiet")
+            .arg(commit)
+            .run()?;
+        ensure_success(&capture_data, &format!("Failed to reset to {commit}"))?;
+
+        self.update_submodules(repo_info)
+    }
+
+    fn remote_head(&self, repo_info: &RepositoryInfo, remote: &str) -> Result<Option<String>> {
+        let branch = default_branch(repo_info, &self.envs, remote)?;
+
+        Ok(branch.map(|branch| format!("refs/remotes/{remote}/{branch}")))
+    }
+
+    fn switch_branch(
+        &self,
+        repo_info: &RepositoryInfo,
+        branch: &str,
+        upstream: &str,
+    ) -> Result<()> {
+        let switch = self.git(repo_info).arg("switch");
+        let switch = if rev_parse(repo_info, &self.envs, &format!("refs/heads/{branch}"))?.is_some()
+        {
+            switch.arg(branch)
+        } else {
+            switch
+                .arg("--create")
+                .arg(branch)
+                .arg("--track")
+                .arg(upstream)
+        };
+        let capture_data = switch.run()?;
+        ensure_success(&capture_data, &format!("Failed to switch to {branch}"))?;
+
+        self.update_submodules(repo_info)
+    }
+
+    fn inactive_branches(&self, repo_info: &RepositoryInfo) -> Result<Vec<String>> {
+        // The worktree path is only set for branches that're checked out somewhere.
+        let capture_data = self
+            .git(repo_info)
+            .arg("for-each-ref")
+            .arg("--format=%(worktreepath)%00%(refname:lstrip=2)")
+            .arg("refs/heads")
+            .run()?;
+        ensure_success(&capture_data, "Couldn't list local branches")?;
+
+        let stdout = String::from_utf8_lossy(&capture_data.stdout);
+        let branches = stdout
+            .lines()
+            .filter_map(|line| line.split_once('\0'))
+            .filter(|(worktree, _)| worktree.is_empty())
+            .map(|(_, branch)| branch.to_string())
+            .collect();
+
+        Ok(branches)
+    }
+
+    fn fast_forward_branch(
+        &self,
+        repo_info: &RepositoryInfo,
+        branch: &str,
+        upstream: &str,
+    ) -> Result<FastForward> {
+        let reference = format!("refs/heads/{branch}");
+        let Some(local) = rev_parse(repo_info, &self.envs, &reference)? else {
+            bail!("Couldn't resolve {reference}");
+        };
+        let Some(target) = rev_parse(repo_info, &self.envs, upstream)? else {
+            bail!("Couldn't resolve {upstream}");
+        };
+
+        if local == target || self.is_ancestor(repo_info, &target, &local)? {
+            return Ok(FastForward::UpToDate);
+        }
+        if !self.is_ancestor(repo_info, &local, &target)? {
+            return Ok(FastForward::NotPossible(format!(
+                "{branch} and {upstream} have diverged"
+            )));
+        }
+
+        // Only update the branch, if nobody moved it in the meantime.
+        let capture_data = self
+            .git(repo_info)
+            .arg("update-ref")
+            .arg("-m")
+            .arg(format!("geil: Fast-forward to {upstream}"))
+            .arg(&reference)
+            .arg(&target)
+            .arg(&local)
+            .run()?;
+        ensure_success(&capture_data, &format!("Failed to update {branch}"))?;
+
+        Ok(FastForward::Updated)
+    }
+
+    fn upstream_gone(&self, repo_info: &RepositoryInfo, branch: &str) -> Result<bool> {
+        let capture_data = self
+            .git(repo_info)
+            .arg("for-each-ref")
+            .arg("--format=%(upstream:track)")
+            .arg(format!("refs/heads/{branch}"))
+            .run()?;
+        ensure_success(&capture_data, &format!("Couldn't get upstream of {branch}"))?;
+
+        Ok(String::from_utf8_lossy(&capture_data.stdout).trim() == "[gone]")
+    }
+
+    fn delete_branch(&self, repo_info: &RepositoryInfo, branch: &str) -> Result<()> {
+        let capture_data = self
+            .git(repo_info)
+            .arg("branch")
+            .arg("--delete")
+            .arg("--force")
+            .arg("--")
+            .arg(branch)
+            .run()?;
+        ensure_success(&capture_data, &format!("Failed to delete {branch}"))
+    }
+
+    fn ahead_behind(&self, repo_info: &RepositoryInfo, upstream: &str) -> Result<(usize, usize)> {
+        self.count_commits(repo_info, "HEAD", upstream)
+    }
+
+    fn branch_ahead_behind(
+        &self,
+        repo_info: &RepositoryInfo,
+        branch: &str,
+        upstream: &str,
+    ) -> Result<(usize, usize)> {
+        self.count_commits(repo_info, &format!("refs/heads/{branch}"), upstream)
+    }
+}
+
+/// Get all remotes of a repository and their urls.
+pub fn get_remotes(
+    repo_info: &RepositoryInfo,
+    envs: &HashMap<String, String>,
+) -> Result<BTreeMap<String, String>> {
+    let capture_data = cmd!("git", "config", "--get-regexp", r"^remote\..*\.url$")
+        .cwd(repo_info.path.clone())
+        .env(envs.clone())
+        .timeout(repo_info.timeout())
+        .run()?;
+
+    // Each line looks like this: `remote.origin.url git@github.com:nukesor/geil`
+    // The command fails, if there're no remotes at all.
+    let mut remotes = BTreeMap::new();
+    let stdout = String::from_utf8_lossy(&capture_data.stdout);
+    for line in stdout.lines().filter(|_| capture_data.success()) {
+        let Some((key, url)) = line.split_once(' ') else {
+            continue;
+        };
+        let Some(remote) = key
+            .strip_prefix("remote.")
+            .and_then(|key| key.strip_suffix(".url"))
+        else {
+            continue;
+        };
+
+        remotes.insert(remote.to_string(), url.to_string());
+    }
+
+    Ok(remotes)
+}
+
+/// Get the default branch of a remote, based on its `HEAD`.
+/// Returns `None`, if the remote `HEAD` isn't known locally.
+pub fn default_branch(
+    repo_info: &RepositoryInfo,
+    envs: &HashMap<String, String>,
+    remote: &str,
+) -> Result<Option<String>> {
+    let capture_data = cmd!(
+        "git",
+        "symbolic-ref",
+        "--quiet",
+        format!("refs/remotes/{remote}/HEAD")
+    )
+    .cwd(repo_info.path.clone())
+    .env(envs.clone())
+    .timeout(repo_info.timeout())
+    .run()?;
+    if !capture_data.success() {
+        return Ok(None);
+    }
+
+    // The output looks like this: `refs/remotes/origin/main`
+    let stdout = String::from_utf8_lossy(&capture_data.stdout);
+    let branch = stdout
+        .trim()
+        .strip_prefix(&format!("refs/remotes/{remote}/"))
+        .map(|branch| branch.to_string());
+
+    Ok(branch)
+}
+
+/// Clone a repository from a manifest entry into the target path and add all of its other
+/// remotes. The `origin` remote is preferred for cloning, if it exists.
+pub fn clone(entry: &ManifestEntry, target: &Path, envs: &HashMap<String, String>) -> Result<()> {
+    let Some((remote, url)) = entry
+        .remotes
+        .get_key_value("origin")
+        .or_else(|| entry.remotes.iter().next())
+    else {
+        bail!("Repository has no remotes to clone from");
+    };
+
+    let mut clone = cmd!("git", "clone", "--origin", remote);
+    if let Some(branch) = &entry.default_branch {
+        clone = clone.arg("--branch").arg(branch);
+    }
+    // Manifests might come from somewhere else, so urls must never be treated as options.
+    // The repository doesn't exist yet, so only the user's global ssh command is respected.
+    let dir = target
+        .ancestors()
+        .find(|dir| dir.is_dir())
+        .unwrap_or(target);
+    let capture_data = clone
+        .arg("--")
+        .arg(url)
+        .arg(target)
+        .env(repository_environment(envs, dir))
+        .run()?;
+    ensure_success(&capture_data, "Failed to clone repository")?;
+
+    // Add all other remotes.
+    for (name, url) in entry.remotes.iter().filter(|(name, _)| *name != remote) {
+        let capture_data = cmd!("git", "remote", "add", "--", name, url)
+            .cwd(target.to_path_buf())
+            .env(envs.clone())
+            .run()?;
+        ensure_success(&capture_data, &format!("Failed to add remote {name}"))?;
+    }
+
+    Ok(())
+}
+
+/// Return an error containing the command's output, if it didn't exit successfully.
+fn ensure_success(capture_data: &Capture, message: &str) -> Result<()> {
+    if capture_data.success() {
+        return Ok(());
+    }
+
+    let output = String::from_utf8_lossy(&capture_data.stdout)
+        .trim()
+        .to_string();
+    if is_authentication_failure(&output) {
+        return Err(AuthenticationRequired { output }.into());
+    }
+
+    bail!("{message}: {output}");
+}
+
+/// Check whether a rebase has been started, but hasn't been finished or aborted yet.
+fn rebase_in_progress(repo_info: &RepositoryInfo, envs: &HashMap<String, String>) -> Result<bool> {
+    for state in ["rebase-merge", "rebase-apply"] {
+        let capture_data = cmd!("git", "rev-parse", "--git-path", state)
+            .cwd(repo_info.path.clone())
+            .env(envs.clone())
+            .timeout(repo_info.timeout())
+            .run()?;
+        ensure_success(&capture_data, "Failed to find the git directory")?;
+
+        let path = String::from_utf8_lossy(&capture_data.stdout)
+            .trim()
+            .to_string();
+        if repo_info.path.join(path).exists() {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
+
+/// Resolve a revision to its commit hash.
+/// Returns `None`, if the revision doesn't exist.
+fn rev_parse(
+    repo_info: &RepositoryInfo,
+    envs: &HashMap<String, String>,
+    revision: &str,
+) -> Result<Option<String>> {
+    let capture_data = cmd!("git", "rev-parse", "--verify", "--quiet", revision)
+        .cwd(repo_info.path.clone())
+        .env(envs.clone())
+        .timeout(repo_info.timeout())
+        .run()?;
+
+    if !capture_data.success() {
+        return Ok(None);
+    }
+
+    Ok(Some(
+        String::from_utf8_lossy(&capture_data.stdout)
+            .trim()
+            .to_string(),
+    ))
+}
+
+/// Get all remote refs and the commits they point to.
+fn remote_refs(repo_info: &RepositoryInfo, envs: &HashMap<String, String>) -> Result<String> {
+    let capture_data = cmd!(
+        "git",
+        "for-each-ref",
+        "--format=%(objectname) %(refname)",
+        "refs/remotes"
+    )
+    .cwd(repo_info.path.clone())
+    .env(envs.clone())
+    .timeout(repo_info.timeout())
+    .run()?;
+    ensure_success(&capture_data, "Couldn't list remote refs")?;
+
+    Ok(String::from_utf8_lossy(&capture_data.stdout).into_owned())
+}
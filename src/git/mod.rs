@@ -0,0 +1,764 @@
+//! Everything that's needed to inspect and update git repositories.
+//!
+//! The actual git operations are done by a [GitBackend]. The functions in this module use the
+//! backend to find out about a repository and record their findings on its [RepositoryInfo].
+use std::{
+    collections::HashMap,
+    env::{var_os, vars},
+    fmt,
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail};
+use log::{debug, info};
+
+use crate::{
+    cmd,
+    config::{Backend, Strategy},
+    repository_info::*,
+};
+
+mod cli;
+#[cfg(feature = "native-git")]
+mod native;
+
+pub use cli::{CliBackend, clone, default_branch, get_remotes};
+#[cfg(feature = "native-git")]
+pub use native::NativeBackend;
+
+/// Output of git and ssh, which indicates that credentials or a confirmation are needed,
+/// but the user couldn't be asked for them.
+const AUTHENTICATION_FAILURES: &[&str] = &[
+    "terminal prompts disabled",
+    "could not read Username",
+    "could not read Password",
+    "Authentication failed",
+    "Permission denied (publickey",
+    "Host key verification failed",
+];
+
+/// The ssh command that's used by git, if ssh mustn't prompt and no other one is configured.
+const BATCH_SSH_COMMAND: &str = "ssh -o BatchMode=yes";
+
+/// The error that's returned, if git needs credentials, but isn't allowed to ask for them.
+#[derive(Debug)]
+pub struct AuthenticationRequired {
+    pub output: String,
+}
+
+impl fmt::Display for AuthenticationRequired {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Authentication required: {}", self.output)
+    }
+}
+
+impl std::error::Error for AuthenticationRequired {}
+
+/// The result of trying to fast-forward or rebase a branch.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FastForward {
+    /// The branch already contains all commits of its upstream.
+    UpToDate,
+    /// The branch has been moved to its upstream.
+    Updated,
+    /// The branch couldn't be updated and is unchanged. Contains the reason.
+    NotPossible(String),
+}
+
+/// Why a local branch isn't needed anymore.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PruneReason {
+    /// The branch's upstream has been deleted on the remote and all of its commits are part of
+    /// the default branch.
+    UpstreamGone,
+    /// All commits of the branch are part of the default branch.
+    Merged,
+    /// The branch's upstream has been deleted on the remote, but some of its commits aren't part
+    /// of the default branch, e.g. because it has been squash merged.
+    /// Deleting such a branch loses those commits.
+    Unmerged,
+}
+
+/// A local branch, which can be deleted.
+#[derive(Debug, Clone)]
+pub struct PrunableBranch {
+    pub name: String,
+    pub reason: PruneReason,
+}
+
+/// The low-level git operations geil needs for checking and updating repositories.
+///
+/// Implementations have to be usable from multiple threads at once, as repositories are
+/// handled in parallel.
+pub trait GitBackend: Send + Sync {
+    /// Get the amount of stash entries.
+    fn stash_count(&self, repo_info: &RepositoryInfo) -> Result<usize>;
+
+    /// Get the changes in the working tree and the index.
+    fn status(&self, repo_info: &RepositoryInfo) -> Result<FileChanges>;
+
+    /// Fetch from all remotes.
+    /// Remote refs that no longer exist on the remote are deleted, if [RepositoryInfo::prune]
+    /// is set. Returns whether any remote ref changed.
+    fn fetch(&self, repo_info: &RepositoryInfo) -> Result<bool>;
+
+    /// Get the name of the currently checked out branch.
+    /// Returns `None`, if the HEAD is detached.
+    fn current_branch(&self, repo_info: &RepositoryInfo) -> Result<Option<String>>;
+
+    /// Get the full name of the ref the given branch should be compared against and updated
+    /// from.
+    ///
+    /// This is the configured upstream of the branch, unless a remote has been set in the
+    /// repository's settings. Returns `None`, if there's no such ref.
+    fn upstream(&self, repo_info: &RepositoryInfo, branch: &str) -> Result<Option<String>>;
+
+    /// Fast-forward the current branch to the given upstream ref, including submodules.
+    fn fast_forward(&self, repo_info: &RepositoryInfo, upstream: &str) -> Result<FastForward>;
+
+    /// Rebase the current branch onto the given upstream ref, including submodules.
+    ///
+    /// Conflicts abort the rebase, which restores the previous state.
+    fn rebase(&self, repo_info: &RepositoryInfo, upstream: &str) -> Result<FastForward>;
+
+    /// Stash all changes of tracked files.
+    /// Returns whether anything has been stashed.
+    fn stash(&self, repo_info: &RepositoryInfo) -> Result<bool>;
+
+    /// Apply and drop the latest stash entry.
+    /// Returns `false`, if it couldn't be applied due to conflicts, in which case it's kept.
+    fn stash_pop(&self, repo_info: &RepositoryInfo) -> Result<bool>;
+
+    /// Get the commit the HEAD points to.
+    fn head_commit(&self, repo_info: &RepositoryInfo) -> Result<String>;
+
+    /// Reset the current branch, the index and the working tree to the given commit.
+    fn reset_hard(&self, repo_info: &RepositoryInfo, commit: &str) -> Result<()>;
+
+    /// Get the full name of the ref the `HEAD` of a remote points to, e.g.
+    /// `refs/remotes/origin/main`. Returns `None`, if the remote `HEAD` isn't known locally.
+    fn remote_head(&self, repo_info: &RepositoryInfo, remote: &str) -> Result<Option<String>>;
+
+    /// Check out a local branch.
+    /// The branch is created from the given upstream ref and tracks it, if it doesn't exist yet.
+    fn switch_branch(&self, repo_info: &RepositoryInfo, branch: &str, upstream: &str)
+    -> Result<()>;
+
+    /// Get the names of all local branches that aren't checked out in any worktree.
+    fn inactive_branches(&self, repo_info: &RepositoryInfo) -> Result<Vec<String>>;
+
+    /// Fast-forward a branch that isn't checked out to the given upstream ref.
+    /// Only the branch itself is moved, as there's no working tree to update.
+    fn fast_forward_branch(
+        &self,
+        repo_info: &RepositoryInfo,
+        branch: &str,
+        upstream: &str,
+    ) -> Result<FastForward>;
+
+    /// Check whether the branch has a configured upstream, which doesn't exist anymore.
+    /// This usually means that it has been deleted on the remote.
+    fn upstream_gone(&self, repo_info: &RepositoryInfo, branch: &str) -> Result<bool>;
+
+    /// Delete a local branch, even if it hasn't been merged.
+    fn delete_branch(&self, repo_info: &RepositoryInfo, branch: &str) -> Result<()>;
+
+    /// Count the commits that're only on HEAD and only on the upstream ref.
+    fn ahead_behind(&self, repo_info: &RepositoryInfo, upstream: &str) -> Result<(usize, usize)>;
+
+    /// Count the commits that're only on the given local branch and only on the upstream ref.
+    fn branch_ahead_behind(
+        &self,
+        repo_info: &RepositoryInfo,
+        branch: &str,
+        upstream: &str,
+    ) -> Result<(usize, usize)>;
+}
+
+/// Create the configured git backend.
+///
+/// The environment is used for all git processes started by the backend.
+pub fn backend(kind: Backend, envs: HashMap<String, String>) -> Result<Box<dyn GitBackend>> {
+    match kind {
+        Backend::Cli => Ok(Box::new(CliBackend::new(envs))),
+        #[cfg(feature = "native-git")]
+        Backend::Native => Ok(Box::new(NativeBackend::new(envs))),
+        #[cfg(not(feature = "native-git"))]
+        Backend::Native => {
+            bail!(
+                "The native git backend isn't available, geil has been built without the `native-git` feature"
+            )
+        }
+    }
+}
+
+/// Get the environment for git commands, which is based on our own environment.
+///
+/// git's messages are always in English, as authentication failures are detected by them and
+/// they'd be translated otherwise. See [command_environment] for everything else.
+pub fn environment(interactive: bool) -> HashMap<String, String> {
+    let mut envs = command_environment(interactive);
+    envs.insert("LC_ALL".into(), "C".into());
+    envs.insert("LANGUAGE".into(), "C".into());
+
+    envs
+}
+
+/// Get the environment for arbitrary commands, which might run git or ssh themselves.
+///
+/// In non-interactive mode, git and ssh fail instead of prompting for credentials or host key
+/// confirmations. Such prompts would otherwise be hidden by the progress bars and hang the run.
+pub fn command_environment(interactive: bool) -> HashMap<String, String> {
+    let mut envs: HashMap<String, String> = vars().collect();
+    if interactive {
+        return envs;
+    }
+
+    envs.insert("GIT_TERMINAL_PROMPT".into(), "0".into());
+    envs.insert("GCM_INTERACTIVE".into(), "never".into());
+
+    // Respect a custom ssh command, but still prevent it from prompting.
+    // Programs set via `GIT_SSH` aren't necessarily ssh, e.g. `plink`, so they're used as they
+    // are. The same goes for `core.sshCommand`, see [repository_environment].
+    if let Some(ssh_command) = envs.get("GIT_SSH_COMMAND").cloned() {
+        envs.insert(
+            "GIT_SSH_COMMAND".into(),
+            format!("{ssh_command} -o BatchMode=yes"),
+        );
+    } else if !envs.contains_key("GIT_SSH") {
+        envs.insert("GIT_SSH_COMMAND".into(), BATCH_SSH_COMMAND.into());
+    }
+
+    envs
+}
+
+/// Get the environment for commands in the given directory, which might connect to a remote.
+///
+/// The ssh command set by [command_environment] takes precedence over `core.sshCommand`.
+/// It's removed again, if git's config in that directory sets its own ssh command, e.g. a
+/// wrapper with another identity file. Such commands are used as they are.
+pub fn repository_environment(
+    envs: &HashMap<String, String>,
+    dir: &Path,
+) -> HashMap<String, String> {
+    let mut envs = envs.clone();
+    let batch_ssh = envs
+        .get("GIT_SSH_COMMAND")
+        .is_some_and(|ssh_command| ssh_command == BATCH_SSH_COMMAND)
+        && var_os("GIT_SSH_COMMAND").is_none();
+    if !batch_ssh {
+        return envs;
+    }
+
+    let configured = cmd!("git", "config", "--get", "core.sshCommand")
+        .cwd(dir.to_path_buf())
+        .env(envs.clone())
+        .run()
+        .is_ok_and(|capture_data| capture_data.success());
+    if configured {
+        envs.remove("GIT_SSH_COMMAND");
+    }
+
+    envs
+}
+
+pub fn get_stashed_entries(repo_info: &mut RepositoryInfo, git: &dyn GitBackend) -> Result<()> {
+    let name = repo_info.name.clone();
+
+    repo_info.stashed = git.stash_count(repo_info)?;
+    if repo_info.stashed == 0 {
+        info!("{name}: No stashed changes");
+        return Ok(());
+    }
+
+    info!("{name}: Found {} stashed entries!", repo_info.stashed);
+    repo_info.add(Finding::Stashed);
+
+    Ok(())
+}
+
+/// Check for local changes in the working tree.
+/// Untracked files can be ignored, in which case repositories with only untracked files are clean.
+pub fn check_local_changes(
+    repo_info: &mut RepositoryInfo,
+    git: &dyn GitBackend,
+    ignore_untracked: bool,
+) -> Result<()> {
+    let name = repo_info.name.clone();
+
+    let changes = git.status(repo_info)?;
+    let is_clean = changes.is_clean(ignore_untracked);
+    repo_info.changes = changes;
+
+    // No local changes, everything seems clean.
+    if is_clean {
+        info!("{name}: No local changes");
+        return Ok(());
+    }
+
+    repo_info.add(Finding::LocalChanges);
+    info!("{name}: Found local changes!");
+
+    Ok(())
+}
+
+pub fn fetch(repo_info: &mut RepositoryInfo, git: &dyn GitBackend) -> Result<()> {
+    let name = repo_info.name.clone();
+
+    if git.fetch(repo_info)? {
+        info!("{name}: Got new changes from remote!");
+        repo_info.outcome = UpdateOutcome::Fetched;
+    } else {
+        info!("{name}: Everything is up to date");
+        repo_info.outcome = UpdateOutcome::UpToDate;
+    }
+
+    Ok(())
+}
+
+/// Get the upstream of the current branch.
+///
+/// If the current HEAD isn't on a branch, the repository gets the `Detached` finding.
+/// If the current branch has no upstream, the repository gets the `NoUpstream` finding.
+fn current_upstream(
+    repo_info: &mut RepositoryInfo,
+    git: &dyn GitBackend,
+) -> Result<Option<String>> {
+    let name = repo_info.name.clone();
+
+    // There's nothing to update from on a detached HEAD.
+    let Some(current_branch) = git.current_branch(repo_info)? else {
+        info!("{name}: Detached HEAD, cannot update");
+        repo_info.add(Finding::Detached);
+        return Ok(None);
+    };
+
+    let upstream = git.upstream(repo_info, &current_branch)?;
+    if upstream.is_none() {
+        info!("{name}: No upstream for branch {current_branch}");
+        repo_info.add(Finding::NoUpstream);
+    }
+
+    Ok(upstream)
+}
+
+/// Update the current branch with the given strategy.
+///
+/// Repositories with local changes are only updated by strategies that stash them.
+pub fn pull(
+    repo_info: &mut RepositoryInfo,
+    git: &dyn GitBackend,
+    strategy: Strategy,
+) -> Result<()> {
+    let dirty = repo_info.has(Finding::LocalChanges);
+    match strategy {
+        Strategy::FfOnly if !dirty => merge(repo_info, git),
+        Strategy::Rebase if !dirty => rebase(repo_info, git),
+        Strategy::RebaseAutostash => stash_update(repo_info, git, true),
+        Strategy::StashPullPop => stash_update(repo_info, git, false),
+        // We cannot update with local changes.
+        Strategy::FfOnly | Strategy::Rebase => {
+            info!("{}: Skipping update due to local changes", repo_info.name);
+            Ok(())
+        }
+    }
+}
+
+pub fn merge(repo_info: &mut RepositoryInfo, git: &dyn GitBackend) -> Result<()> {
+    let name = repo_info.name.clone();
+
+    let Some(upstream) = current_upstream(repo_info, git)? else {
+        return Ok(());
+    };
+
+    match git.fast_forward(repo_info, &upstream)? {
+        FastForward::UpToDate => {
+            info!("{name}: Already up to date");
+            repo_info.outcome = UpdateOutcome::UpToDate;
+        }
+        FastForward::Updated => {
+            info!("{name}: Fast forward succeeded");
+            repo_info.outcome = UpdateOutcome::Updated;
+        }
+        FastForward::NotPossible(reason) => {
+            info!("{name}: Fast forward not possible: {reason}");
+            repo_info.add(Finding::NoFastForward);
+        }
+    }
+
+    Ok(())
+}
+
+/// Rebase the current branch onto its upstream.
+pub fn rebase(repo_info: &mut RepositoryInfo, git: &dyn GitBackend) -> Result<()> {
+    let name = repo_info.name.clone();
+
+    let Some(upstream) = current_upstream(repo_info, git)? else {
+        return Ok(());
+    };
+
+    match git.rebase(repo_info, &upstream)? {
+        FastForward::UpToDate => {
+            info!("{name}: Already up to date");
+            repo_info.outcome = UpdateOutcome::UpToDate;
+        }
+        FastForward::Updated => {
+            info!("{name}: Rebase succeeded");
+            repo_info.outcome = UpdateOutcome::Updated;
+        }
+        FastForward::NotPossible(reason) => {
+            info!("{name}: Rebase has been rolled back: {reason}");
+            repo_info.outcome = UpdateOutcome::RolledBack;
+            repo_info.add(Finding::UpdateConflict);
+        }
+    }
+
+    Ok(())
+}
+
+/// Stash all local changes, update the current branch and restore the changes again.
+/// The branch is rebased onto its upstream, if `rebase` is set, otherwise it's fast-forwarded.
+/// If the changes conflict with the new commits, the update is rolled back.
+pub fn stash_update(
+    repo_info: &mut RepositoryInfo,
+    git: &dyn GitBackend,
+    rebase: bool,
+) -> Result<()> {
+    let name = repo_info.name.clone();
+    let kind = if rebase { "Rebase" } else { "Fast forward" };
+
+    let Some(upstream) = current_upstream(repo_info, git)? else {
+        return Ok(());
+    };
+
+    let head = git.head_commit(repo_info)?;
+    // Only tracked files are stashed, so there might be nothing to restore afterwards.
+    let stashed = git.stash(repo_info)?;
+    if stashed {
+        debug!("{name}: Stashed local changes");
+    }
+
+    let update = if rebase {
+        git.rebase(repo_info, &upstream)
+    } else {
+        git.fast_forward(repo_info, &upstream)
+    };
+    let result = match update {
+        Ok(result) => result,
+        Err(err) => {
+            // Try to restore the local changes, they're safe in the stash otherwise.
+            if stashed && let Err(restore_err) = restore_stash(repo_info, git) {
+                return Err(err.context(format!("{restore_err:#}")));
+            }
+            return Err(err);
+        }
+    };
+
+    // The HEAD didn't move, so the stash always applies cleanly.
+    if !matches!(result, FastForward::Updated) && stashed {
+        restore_stash(repo_info, git)?;
+    }
+
+    match result {
+        FastForward::UpToDate => {
+            info!("{name}: Already up to date");
+            repo_info.outcome = UpdateOutcome::UpToDate;
+        }
+        // Conflicting rebases are aborted, which restores the previous commit.
+        FastForward::NotPossible(reason) if rebase => {
+            info!("{name}: Rebase has been rolled back: {reason}");
+            repo_info.outcome = UpdateOutcome::RolledBack;
+            repo_info.add(Finding::UpdateConflict);
+        }
+        FastForward::NotPossible(reason) => {
+            info!("{name}: Fast forward not possible: {reason}");
+            repo_info.add(Finding::NoFastForward);
+        }
+        FastForward::Updated if !stashed => {
+            info!("{name}: {kind} succeeded");
+            repo_info.outcome = UpdateOutcome::Updated;
+        }
+        FastForward::Updated => {
+            let applied = git
+                .stash_pop(repo_info)
+                .context("Local changes couldn't be restored, they're still stashed")?;
+            if applied {
+                info!("{name}: {kind} with stashed changes succeeded");
+                repo_info.outcome = UpdateOutcome::UpdatedWithStash;
+                return Ok(());
+            }
+
+            // The local changes conflict with the new commits.
+            // Go back to the previous commit, on which the stash applies cleanly.
+            info!("{name}: Local changes conflict with the new commits, rolling back");
+            git.reset_hard(repo_info, &head)?;
+            restore_stash(repo_info, git)?;
+            repo_info.outcome = UpdateOutcome::RolledBack;
+            repo_info.add(Finding::UpdateConflict);
+        }
+    }
+
+    Ok(())
+}
+
+/// Restore the stashed local changes on a commit they apply cleanly to.
+/// Fails, if they couldn't be applied, in which case they're still in the stash.
+fn restore_stash(repo_info: &RepositoryInfo, git: &dyn GitBackend) -> Result<()> {
+    let applied = git
+        .stash_pop(repo_info)
+        .context("Local changes couldn't be restored, they're still stashed")?;
+    if !applied {
+        bail!("Local changes couldn't be restored, they're still stashed");
+    }
+
+    Ok(())
+}
+
+/// Fast-forward all local branches that aren't checked out to their upstreams.
+/// The checked out branch is handled by [merge], as its working tree has to be updated as well.
+pub fn update_branches(repo_info: &mut RepositoryInfo, git: &dyn GitBackend) -> Result<()> {
+    let name = repo_info.name.clone();
+
+    for branch in git.inactive_branches(repo_info)? {
+        let Some(upstream) = git.upstream(repo_info, &branch)? else {
+            debug!("{name}: No upstream for branch {branch}");
+            continue;
+        };
+
+        let outcome = match git.fast_forward_branch(repo_info, &branch, &upstream)? {
+            FastForward::UpToDate => BranchOutcome::UpToDate,
+            FastForward::Updated => {
+                info!("{name}: Fast forwarded {branch} to {upstream}");
+                BranchOutcome::Updated
+            }
+            FastForward::NotPossible(reason) => {
+                info!("{name}: Fast forward of {branch} not possible: {reason}");
+                BranchOutcome::NoFastForward
+            }
+        };
+
+        repo_info.branches.push(BranchUpdate {
+            name: branch,
+            upstream,
+            outcome,
+        });
+    }
+
+    Ok(())
+}
+
+/// Check whether the current branch has some commits that're newer than its upstream and vice
+/// versa. If the current HEAD isn't on a branch, the repository gets the `Detached` finding.
+/// If the current branch has no upstream, the repository gets the `NoUpstream` finding.
+pub fn check_unpushed_commits(repo_info: &mut RepositoryInfo, git: &dyn GitBackend) -> Result<()> {
+    let name = repo_info.name.clone();
+
+    // The repository is in a detached state. Return early.
+    let Some(current_branch) = git.current_branch(repo_info)? else {
+        repo_info.add(Finding::Detached);
+        return Ok(());
+    };
+
+    let Some(upstream) = git.upstream(repo_info, &current_branch)? else {
+        info!("{name}: No upstream for branch {current_branch}");
+        repo_info.add(Finding::NoUpstream);
+        return Ok(());
+    };
+
+    // Count the commits that're only on the local branch and only on the upstream.
+    debug!("{name}: Comparing {current_branch} with {upstream}");
+    let (ahead, behind) = git.ahead_behind(repo_info, &upstream)?;
+    repo_info.ahead = Some(ahead);
+    repo_info.behind = Some(behind);
+
+    debug!("{name}: Ahead: {ahead}, Behind: {behind}");
+    if ahead > 0 {
+        info!("{name}: Found unpushed commits!");
+        repo_info.add(Finding::NotPushed);
+    }
+    if behind > 0 {
+        info!("{name}: Upstream has new commits!");
+        repo_info.add(Finding::Behind);
+    }
+
+    Ok(())
+}
+
+/// Compare the current branch with the default branch of its remote.
+///
+/// Repositories that aren't on the default branch get the `NotOnDefaultBranch` finding.
+/// If all of the branch's commits are part of the default branch, it has been merged and the
+/// repository also gets the `Merged` finding. Squash merges and rebases cannot be detected.
+///
+/// With `switch_merged`, the default branch is checked out instead of a merged branch, as long
+/// as there aren't any local changes.
+pub fn check_default_branch(
+    repo_info: &mut RepositoryInfo,
+    git: &dyn GitBackend,
+    switch_merged: bool,
+) -> Result<()> {
+    let name = repo_info.name.clone();
+
+    // Detached repositories are already reported on their own.
+    let Some(current_branch) = git.current_branch(repo_info)? else {
+        return Ok(());
+    };
+
+    let Some((default_branch, default_ref)) =
+        remote_default_branch(repo_info, git, Some(&current_branch))?
+    else {
+        debug!("{name}: Default branch is unknown");
+        return Ok(());
+    };
+    repo_info.default_branch = Some(default_branch.clone());
+    if current_branch == default_branch {
+        return Ok(());
+    }
+
+    let (ahead, behind) = git.ahead_behind(repo_info, &default_ref)?;
+    let merged = is_merged(
+        repo_info,
+        git,
+        &current_branch,
+        &default_ref,
+        (ahead, behind),
+    )?;
+    debug!("{name}: {current_branch} is {behind} commits behind {default_ref}, merged: {merged}");
+
+    if merged && switch_merged && !repo_info.has(Finding::LocalChanges) {
+        info!("{name}: Switching from merged branch {current_branch} to {default_branch}");
+        git.switch_branch(repo_info, &default_branch, &default_ref)?;
+        repo_info.switched_from = Some(current_branch);
+        repo_info.add(Finding::Switched);
+        return Ok(());
+    }
+
+    info!("{name}: Not on default branch {default_branch}");
+    repo_info.behind_default = Some(behind);
+    repo_info.add(Finding::NotOnDefaultBranch);
+    if merged {
+        info!("{name}: {current_branch} has been merged into {default_branch}");
+        repo_info.add(Finding::Merged);
+    }
+
+    Ok(())
+}
+
+/// Find all local branches that aren't needed anymore, because their upstream is gone or
+/// they've been merged into the default branch.
+/// Checked out branches and the default branch itself are never pruned.
+pub fn prunable_branches(
+    repo_info: &RepositoryInfo,
+    git: &dyn GitBackend,
+) -> Result<Vec<PrunableBranch>> {
+    let current_branch = git.current_branch(repo_info)?;
+    let default_branch = remote_default_branch(repo_info, git, current_branch.as_deref())?;
+
+    let mut branches = Vec::new();
+    for branch in git.inactive_branches(repo_info)? {
+        let counts = match &default_branch {
+            Some((default_branch, _)) if *default_branch == branch => continue,
+            Some((_, default_ref)) => {
+                Some(git.branch_ahead_behind(repo_info, &branch, default_ref)?)
+            }
+            None => None,
+        };
+
+        // Branches with a gone upstream have been pushed before, so they're safe to delete as
+        // long as all of their commits are part of the default branch.
+        let reason = if git.upstream_gone(repo_info, &branch)? {
+            match counts {
+                Some((0, _)) => PruneReason::UpstreamGone,
+                _ => PruneReason::Unmerged,
+            }
+        } else {
+            match (&default_branch, counts) {
+                (Some((_, default_ref)), Some(counts))
+                    if is_merged(repo_info, git, &branch, default_ref, counts)? =>
+                {
+                    PruneReason::Merged
+                }
+                _ => continue,
+            }
+        };
+
+        branches.push(PrunableBranch {
+            name: branch,
+            reason,
+        });
+    }
+
+    Ok(branches)
+}
+
+/// Check whether a branch has been merged into the default branch, given the amount of commits
+/// that're only on the branch and only on the default branch.
+///
+/// A branch that points to the tip of the default branch, e.g. because it has just been
+/// created, has only been merged, if its own upstream has been merged as well.
+fn is_merged(
+    repo_info: &RepositoryInfo,
+    git: &dyn GitBackend,
+    branch: &str,
+    default_ref: &str,
+    (ahead, behind): (usize, usize),
+) -> Result<bool> {
+    if ahead > 0 {
+        return Ok(false);
+    }
+    if behind > 0 {
+        return Ok(true);
+    }
+
+    match git.upstream(repo_info, branch)? {
+        Some(upstream) if upstream != default_ref => {
+            Ok(git.branch_ahead_behind(repo_info, branch, &upstream)?.1 == 0)
+        }
+        _ => Ok(false),
+    }
+}
+
+/// Get the default branch of a remote and the full name of its remote ref, if it's known.
+///
+/// The remote from the repository's settings is used. Otherwise, it's the remote the given
+/// branch is tracking, falling back to `origin`.
+fn remote_default_branch(
+    repo_info: &RepositoryInfo,
+    git: &dyn GitBackend,
+    branch: Option<&str>,
+) -> Result<Option<(String, String)>> {
+    let upstream = match branch {
+        Some(branch) => git.upstream(repo_info, branch)?,
+        None => None,
+    };
+    let remote = repo_info
+        .remote
+        .clone()
+        .or_else(|| upstream.as_deref().and_then(remote_name).map(String::from))
+        .unwrap_or_else(|| "origin".to_string());
+
+    let Some(default_ref) = git.remote_head(repo_info, &remote)? else {
+        return Ok(None);
+    };
+    let default_branch = default_ref
+        .strip_prefix(&format!("refs/remotes/{remote}/"))
+        .map(String::from);
+
+    Ok(default_branch.map(|branch| (branch, default_ref)))
+}
+
+/// Get the name of the remote from the full name of a remote ref.
+/// For example, `origin` for `refs/remotes/origin/main`.
+fn remote_name(reference: &str) -> Option<&str> {
+    reference
+        .strip_prefix("refs/remotes/")
+        .and_then(|reference| reference.split_once('/'))
+        .map(|(remote, _)| remote)
+}
+
+/// Check whether the output of a failed git command means that credentials are missing.
+fn is_authentication_failure(output: &str) -> bool {
+    AUTHENTICATION_FAILURES
+        .iter()
+        .any(|failure| output.contains(failure))
+}
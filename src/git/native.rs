@@ -0,0 +1,566 @@
+//! An in-process backend based on libgit2, which doesn't spawn a process per operation.
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, HashMap},
+    path::Path,
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Context, Result, bail};
+use git2::{
+    AutotagOption, BranchType, Cred, CredentialType, ErrorClass, ErrorCode, FetchOptions,
+    FetchPrune, Oid, RemoteCallbacks, Repository, Status, StatusOptions, build::CheckoutBuilder,
+};
+
+use super::{
+    AuthenticationRequired, CliBackend, FastForward, GitBackend, is_authentication_failure,
+    repository_environment,
+};
+use crate::{
+    cmd,
+    process::{Interrupted, Timeout, interrupted},
+    repository_info::{FileChanges, RepositoryInfo},
+};
+
+/// How often libgit2 may ask for credentials during a single fetch.
+/// It asks again after each rejected credential, which would otherwise loop forever.
+const MAX_CREDENTIAL_ATTEMPTS: usize = 3;
+
+/// How often a running fetch is checked for timeouts and interrupts.
+const ABANDON_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A [GitBackend] that uses libgit2.
+///
+/// Checks don't start any processes, which makes them a lot faster for many repositories.
+/// Credentials for fetching are taken from the ssh-agent and git's credential helpers,
+/// the user is never prompted for them.
+pub struct NativeBackend {
+    /// The environment for the operations that still need the git binary,
+    /// e.g. updating submodules or rebasing.
+    envs: HashMap<String, String>,
+}
+
+impl NativeBackend {
+    pub fn new(envs: HashMap<String, String>) -> NativeBackend {
+        NativeBackend { envs }
+    }
+
+    /// The git binary, for everything libgit2 doesn't do well.
+    fn cli(&self) -> CliBackend {
+        CliBackend::new(self.envs.clone())
+    }
+}
+
+impl GitBackend for NativeBackend {
+    fn stash_count(&self, repo_info: &RepositoryInfo) -> Result<usize> {
+        let repo = open(repo_info)?;
+
+        // There are no stashes, if the stash ref doesn't exist.
+        if repo.find_reference("refs/stash").is_err() {
+            return Ok(0);
+        }
+
+        Ok(repo
+            .reflog("refs/stash")
+            .context("Couldn't count stash entries")?
+            .len())
+    }
+
+    fn status(&self, repo_info: &RepositoryInfo) -> Result<FileChanges> {
+        let repo = open(repo_info)?;
+
+        // Behave like `git status`, which shows untracked directories as a single entry.
+        let mut options = StatusOptions::new();
+        options
+            .include_untracked(true)
+            .recurse_untracked_dirs(false)
+            .renames_head_to_index(true);
+        let statuses = repo
+            .statuses(Some(&mut options))
+            .context("Couldn't get repository status")?;
+
+        let mut changes = FileChanges::default();
+        for entry in statuses.iter() {
+            let status = entry.status();
+            if status.is_conflicted() {
+                changes.conflicted += 1;
+                continue;
+            }
+            if status.is_wt_new() {
+                changes.untracked += 1;
+                continue;
+            }
+            if status.intersects(
+                Status::INDEX_NEW
+                    | Status::INDEX_MODIFIED
+                    | Status::INDEX_DELETED
+                    | Status::INDEX_RENAMED
+                    | Status::INDEX_TYPECHANGE,
+            ) {
+                changes.staged += 1;
+            }
+            if status.intersects(
+                Status::WT_MODIFIED
+                    | Status::WT_DELETED
+                    | Status::WT_RENAMED
+                    | Status::WT_TYPECHANGE,
+            ) {
+                changes.modified += 1;
+            }
+        }
+
+        Ok(changes)
+    }
+
+    fn fetch(&self, repo_info: &RepositoryInfo) -> Result<bool> {
+        let repo = open(repo_info)?;
+
+        // Remember the remote refs, so we can detect whether anything changed.
+        let refs_before = remote_refs(&repo)?;
+
+        let timeout = repo_info.timeout();
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+
+        // libgit2 only calls our callbacks once data is transferred, so a connection that hangs
+        // before that, e.g. during the ssh handshake, would never time out. That's why the fetch
+        // runs on its own thread, which is abandoned once the deadline passed or the user
+        // interrupted the run. The callbacks stop it, as soon as it makes any progress.
+        let (sender, receiver) = mpsc::channel();
+        let path = repo_info.path.clone();
+        let prune = repo_info.prune;
+        thread::spawn(move || {
+            let _ = sender.send(fetch_remotes(&path, prune, timeout, deadline));
+        });
+
+        loop {
+            match receiver.recv_timeout(ABANDON_CHECK_INTERVAL) {
+                Ok(result) => {
+                    result?;
+                    break;
+                }
+                Err(RecvTimeoutError::Timeout) if interrupted() => return Err(Interrupted.into()),
+                Err(RecvTimeoutError::Timeout) => {
+                    if let (Some(timeout), Some(deadline)) = (timeout, deadline)
+                        && Instant::now() >= deadline
+                    {
+                        return Err(Timeout {
+                            command: "fetch".into(),
+                            timeout,
+                        }
+                        .into());
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => bail!("Fetch stopped unexpectedly"),
+            }
+        }
+
+        Ok(refs_before != remote_refs(&repo)?)
+    }
+
+    fn current_branch(&self, repo_info: &RepositoryInfo) -> Result<Option<String>> {
+        let repo = open(repo_info)?;
+
+        // Read `HEAD` directly, so branches without any commits are handled as well.
+        let head = repo.find_reference("HEAD").context("Couldn't read HEAD")?;
+        let branch = head
+            .symbolic_target()
+            .and_then(|target| target.strip_prefix("refs/heads/"))
+            .map(|branch| branch.to_string());
+
+        Ok(branch)
+    }
+
+    fn upstream(&self, repo_info: &RepositoryInfo, branch: &str) -> Result<Option<String>> {
+        let repo = open(repo_info)?;
+
+        let upstream = match &repo_info.remote {
+            // Use the remote branch with the same name on the configured remote.
+            Some(remote) => format!("refs/remotes/{remote}/{branch}"),
+            None => match repo.branch_upstream_name(&format!("refs/heads/{branch}")) {
+                Ok(upstream) => upstream
+                    .as_str()
+                    .context("Upstream name isn't valid utf-8")?
+                    .to_string(),
+                Err(error) if error.code() == ErrorCode::NotFound => return Ok(None),
+                Err(error) => return Err(error).context("Couldn't get upstream"),
+            },
+        };
+
+        // The upstream might be configured, but not fetched yet.
+        if repo.refname_to_id(&upstream).is_err() {
+            return Ok(None);
+        }
+
+        Ok(Some(upstream))
+    }
+
+    fn fast_forward(&self, repo_info: &RepositoryInfo, upstream: &str) -> Result<FastForward> {
+        let repo = open(repo_info)?;
+
+        let head = repo.head().context("Couldn't read HEAD")?;
+        let branch = head
+            .name()
+            .context("Branch name isn't valid utf-8")?
+            .to_string();
+        let local = head.target().context("HEAD doesn't point to a commit")?;
+        let target = repo
+            .refname_to_id(upstream)
+            .context(format!("Couldn't resolve {upstream}"))?;
+
+        if local == target || repo.graph_descendant_of(local, target)? {
+            return Ok(FastForward::UpToDate);
+        }
+        if !repo.graph_descendant_of(target, local)? {
+            return Ok(FastForward::NotPossible(format!(
+                "{branch} and {upstream} have diverged"
+            )));
+        }
+
+        // Update the working tree first. A safe checkout refuses to overwrite local changes,
+        // e.g. untracked files that would be replaced by tracked ones.
+        let commit = repo.find_commit(target)?;
+        let checkout = repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()));
+        match checkout {
+            Ok(()) => (),
+            Err(error) if error.code() == ErrorCode::Conflict => {
+                return Ok(FastForward::NotPossible(error.message().to_string()));
+            }
+            Err(error) => return Err(error).context("Failed to check out the new commits"),
+        }
+
+        repo.find_reference(&branch)?
+            .set_target(target, &format!("geil: Fast-forward to {upstream}"))
+            .context(format!("Failed to update {branch}"))?;
+
+        // libgit2 cannot update nested submodules, so git itself takes care of them.
+        if !repo.submodules()?.is_empty() {
+            let capture_data = cmd!("git", "submodule", "update", "--init", "--recursive")
+                .cwd(repo_info.path.clone())
+                .env(repository_environment(&self.envs, &repo_info.path))
+                .timeout(repo_info.timeout())
+                .run()?;
+            if !capture_data.success() {
+                bail!(
+                    "Failed to update submodules: {}",
+                    String::from_utf8_lossy(&capture_data.stdout).trim()
+                );
+            }
+        }
+
+        Ok(FastForward::Updated)
+    }
+
+    // Rebasing and stashing change the working tree in a lot more ways than fast-forwarding,
+    // which is left to git itself.
+    fn rebase(&self, repo_info: &RepositoryInfo, upstream: &str) -> Result<FastForward> {
+        self.cli().rebase(repo_info, upstream)
+    }
+
+    fn stash(&self, repo_info: &RepositoryInfo) -> Result<bool> {
+        self.cli().stash(repo_info)
+    }
+
+    fn stash_pop(&self, repo_info: &RepositoryInfo) -> Result<bool> {
+        self.cli().stash_pop(repo_info)
+    }
+
+    fn head_commit(&self, repo_info: &RepositoryInfo) -> Result<String> {
+        let repo = open(repo_info)?;
+
+        let head = repo
+            .refname_to_id("HEAD")
+            .context("HEAD doesn't point to a commit")?;
+
+        Ok(head.to_string())
+    }
+
+    fn reset_hard(&self, repo_info: &RepositoryInfo, commit: &str) -> Result<()> {
+        self.cli().reset_hard(repo_info, commit)
+    }
+
+    fn remote_head(&self, repo_info: &RepositoryInfo, remote: &str) -> Result<Option<String>> {
+        let repo = open(repo_info)?;
+
+        let Ok(head) = repo.find_reference(&format!("refs/remotes/{remote}/HEAD")) else {
+            return Ok(None);
+        };
+
+        Ok(head.symbolic_target().map(|target| target.to_string()))
+    }
+
+    fn switch_branch(
+        &self,
+        repo_info: &RepositoryInfo,
+        branch: &str,
+        upstream: &str,
+    ) -> Result<()> {
+        self.cli().switch_branch(repo_info, branch, upstream)
+    }
+
+    fn inactive_branches(&self, repo_info: &RepositoryInfo) -> Result<Vec<String>> {
+        let repo = open(repo_info)?;
+
+        // Collect the branches that're checked out in the main or any linked worktree.
+        let mut checked_out = vec![head_target(&repo)?];
+        for name in repo
+            .worktrees()
+            .context("Couldn't list worktrees")?
+            .iter()
+            .flatten()
+        {
+            let worktree = repo.find_worktree(name)?;
+            if let Ok(worktree_repo) = Repository::open_from_worktree(&worktree) {
+                checked_out.push(head_target(&worktree_repo)?);
+            }
+        }
+
+        let mut branches = Vec::new();
+        for branch in repo
+            .branches(Some(BranchType::Local))
+            .context("Couldn't list local branches")?
+        {
+            let (branch, _) = branch?;
+            let Some(name) = branch.get().name() else {
+                continue;
+            };
+            if checked_out.iter().flatten().any(|head| head == name) {
+                continue;
+            }
+            if let Some(name) = name.strip_prefix("refs/heads/") {
+                branches.push(name.to_string());
+            }
+        }
+
+        Ok(branches)
+    }
+
+    fn fast_forward_branch(
+        &self,
+        repo_info: &RepositoryInfo,
+        branch: &str,
+        upstream: &str,
+    ) -> Result<FastForward> {
+        let repo = open(repo_info)?;
+
+        let mut reference = repo
+            .find_reference(&format!("refs/heads/{branch}"))
+            .context(format!("Couldn't find branch {branch}"))?;
+        let local = reference
+            .target()
+            .context(format!("{branch} doesn't point to a commit"))?;
+        let target = repo
+            .refname_to_id(upstream)
+            .context(format!("Couldn't resolve {upstream}"))?;
+
+        if local == target || repo.graph_descendant_of(local, target)? {
+            return Ok(FastForward::UpToDate);
+        }
+        if !repo.graph_descendant_of(target, local)? {
+            return Ok(FastForward::NotPossible(format!(
+                "{branch} and {upstream} have diverged"
+            )));
+        }
+
+        reference
+            .set_target(target, &format!("geil: Fast-forward to {upstream}"))
+            .context(format!("Failed to update {branch}"))?;
+
+        Ok(FastForward::Updated)
+    }
+
+    fn upstream_gone(&self, repo_info: &RepositoryInfo, branch: &str) -> Result<bool> {
+        let repo = open(repo_info)?;
+
+        // Branches without a configured upstream can't have lost it.
+        let Ok(upstream) = repo.branch_upstream_name(&format!("refs/heads/{branch}")) else {
+            return Ok(false);
+        };
+        let Some(upstream) = upstream.as_str() else {
+            return Ok(false);
+        };
+
+        Ok(repo.refname_to_id(upstream).is_err())
+    }
+
+    fn delete_branch(&self, repo_info: &RepositoryInfo, branch: &str) -> Result<()> {
+        let repo = open(repo_info)?;
+
+        repo.find_branch(branch, BranchType::Local)
+            .context(format!("Couldn't find branch {branch}"))?
+            .delete()
+            .context(format!("Failed to delete {branch}"))
+    }
+
+    fn ahead_behind(&self, repo_info: &RepositoryInfo, upstream: &str) -> Result<(usize, usize)> {
+        let repo = open(repo_info)?;
+
+        let local = repo
+            .refname_to_id("HEAD")
+            .context("HEAD doesn't point to a commit")?;
+
+        count_commits(&repo, local, upstream)
+    }
+
+    fn branch_ahead_behind(
+        &self,
+        repo_info: &RepositoryInfo,
+        branch: &str,
+        upstream: &str,
+    ) -> Result<(usize, usize)> {
+        let repo = open(repo_info)?;
+
+        let local = repo
+            .refname_to_id(&format!("refs/heads/{branch}"))
+            .context(format!("Couldn't resolve {branch}"))?;
+
+        count_commits(&repo, local, upstream)
+    }
+}
+
+/// Count the commits that're only on the local commit and only on the upstream ref.
+fn count_commits(repo: &Repository, local: Oid, upstream: &str) -> Result<(usize, usize)> {
+    let upstream_id = repo
+        .refname_to_id(upstream)
+        .context(format!("Couldn't resolve {upstream}"))?;
+
+    repo.graph_ahead_behind(local, upstream_id)
+        .context(format!("Couldn't compare with {upstream}"))
+}
+
+/// Open the repository.
+fn open(repo_info: &RepositoryInfo) -> Result<Repository> {
+    Repository::open(&repo_info.path)
+        .context(format!("Couldn't open repository {:?}", repo_info.path))
+}
+
+/// Fetch from all remotes of the repository at the given path.
+/// All remotes are fetched in one go, like `git fetch --all` does.
+fn fetch_remotes(
+    path: &Path,
+    prune: bool,
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+) -> Result<()> {
+    let repo = Repository::open(path).context(format!("Couldn't open repository {path:?}"))?;
+
+    let remotes = repo.remotes().context("Couldn't list remotes")?;
+    for name in remotes.iter().flatten() {
+        let mut remote = repo
+            .find_remote(name)
+            .context(format!("Couldn't find remote {name}"))?;
+
+        let mut options = FetchOptions::new();
+        options
+            .remote_callbacks(callbacks(&repo, deadline))
+            .download_tags(AutotagOption::Auto);
+        if prune {
+            options.prune(FetchPrune::On);
+        }
+
+        // An empty list of refspecs uses the configured ones, like `git fetch` does.
+        if let Err(error) = remote.fetch(&[] as &[&str], Some(&mut options), None) {
+            return Err(fetch_error(error, timeout, name));
+        }
+    }
+
+    Ok(())
+}
+
+/// Get the full name of the branch HEAD points to, if it isn't detached.
+fn head_target(repo: &Repository) -> Result<Option<String>> {
+    let head = repo.find_reference("HEAD").context("Couldn't read HEAD")?;
+
+    Ok(head.symbolic_target().map(|target| target.to_string()))
+}
+
+/// Get all remote refs and the commits they point to.
+fn remote_refs(repo: &Repository) -> Result<BTreeMap<String, Oid>> {
+    let mut refs = BTreeMap::new();
+    for reference in repo
+        .references_glob("refs/remotes/*")
+        .context("Couldn't list remote refs")?
+    {
+        let reference = reference?;
+        if let (Some(name), Some(target)) = (reference.name(), reference.target()) {
+            refs.insert(name.to_string(), target);
+        }
+    }
+
+    Ok(refs)
+}
+
+/// The callbacks for fetching, which provide credentials and stop the transfer on timeouts and
+/// interrupts.
+fn callbacks<'a>(repo: &Repository, deadline: Option<Instant>) -> RemoteCallbacks<'a> {
+    let config = repo.config().ok();
+    let attempts = Cell::new(0);
+
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |url, username, allowed| {
+        attempts.set(attempts.get() + 1);
+        if attempts.get() > MAX_CREDENTIAL_ATTEMPTS {
+            return Err(git2::Error::new(
+                ErrorCode::Auth,
+                ErrorClass::Net,
+                "None of the available credentials have been accepted",
+            ));
+        }
+
+        if allowed.contains(CredentialType::SSH_KEY) {
+            return Cred::ssh_key_from_agent(username.unwrap_or("git"));
+        }
+        if allowed.contains(CredentialType::USER_PASS_PLAINTEXT)
+            && let Some(config) = &config
+        {
+            // Without a credential helper, the git binary would have to prompt for them.
+            return Cred::credential_helper(config, url, username).map_err(|_| {
+                git2::Error::new(
+                    ErrorCode::Auth,
+                    ErrorClass::Net,
+                    format!("No credentials available for {url}"),
+                )
+            });
+        }
+
+        Cred::default()
+    });
+
+    // Returning `false` aborts the transfer.
+    let keep_going =
+        move || !interrupted() && deadline.is_none_or(|deadline| Instant::now() < deadline);
+    callbacks.transfer_progress(move |_| keep_going());
+    callbacks.sideband_progress(move |_| keep_going());
+
+    callbacks
+}
+
+/// Turn a failed fetch into the same errors the git binary would cause.
+fn fetch_error(error: git2::Error, timeout: Option<Duration>, remote: &str) -> anyhow::Error {
+    if interrupted() {
+        return Interrupted.into();
+    }
+
+    // The transfer has been aborted by our callbacks.
+    if error.code() == ErrorCode::User
+        && let Some(timeout) = timeout
+    {
+        return Timeout {
+            command: format!("fetch {remote}"),
+            timeout,
+        }
+        .into();
+    }
+
+    if matches!(error.code(), ErrorCode::Auth | ErrorCode::Certificate)
+        || is_authentication_failure(error.message())
+    {
+        return AuthenticationRequired {
+            output: error.message().to_string(),
+        }
+        .into();
+    }
+
+    anyhow::Error::new(error).context(format!("Failed to fetch from remote {remote}"))
+}
@@ -0,0 +1,14 @@
+//! The internals of the `geil` binary.
+//! They're exposed as a library, so they can be used by the integration tests.
+pub mod cli;
+pub mod commands;
+pub mod config;
+pub mod discovery;
+pub mod display;
+pub mod filter;
+pub mod git;
+pub mod manifest;
+pub mod process;
+pub mod repository_info;
+pub mod ssh_key;
+pub mod state;
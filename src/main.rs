@@ -1,23 +1,15 @@
 use anyhow::{Context, Result};
 use clap::Parser;
-use cli::{CliArguments, SubCommand};
+use geil::{
+    cli::{CliArguments, KeysCommand, SubCommand},
+    commands,
+    config::GeilConfig,
+    filter::RepositoryFilter,
+    ssh_key::load_keys,
+    state::State,
+};
 use simplelog::{Config, LevelFilter, SimpleLogger};
 
-mod cli;
-mod commands;
-mod config;
-mod display;
-mod git;
-mod process;
-mod repository_info;
-mod ssh_key;
-mod state;
-
-use ssh_key::load_keys;
-use state::State;
-
-use crate::config::GeilConfig;
-
 fn main() -> Result<()> {
     // Parse commandline options.
     let opt = CliArguments::parse();
@@ -31,31 +23,98 @@ fn main() -> Result<()> {
     };
     SimpleLogger::init(level, Config::default()).unwrap();
 
-    let mut state = State::load().context("Failed to parse state")?;
-    let config = GeilConfig::load().context("Failed to parse config")?;
+    let mut state = State::load(opt.state).context("Failed to parse state")?;
+    let config = GeilConfig::load(opt.config).context("Failed to parse config")?;
 
     match opt.cmd {
         SubCommand::Add { repos } => commands::add(&mut state, repos),
         SubCommand::Remove { repos } => commands::remove(&mut state, repos),
         SubCommand::Ignore { directories } => commands::ignore(&mut state, &directories),
-        SubCommand::Info => commands::print_info(&config, &state),
-        SubCommand::Update {
-            all,
+        SubCommand::Watch {
+            directories,
+            list,
+            max_depth,
+            symlinks,
+        } => commands::watch(&mut state, &config, &directories, list, max_depth, symlinks),
+        SubCommand::Unwatch { directories } => commands::unwatch(&mut state, &config, &directories),
+        SubCommand::Scan => commands::scan(&mut state, &config),
+        SubCommand::Keys { cmd } => match cmd {
+            KeysCommand::Add {
+                path,
+                name,
+                required,
+            } => commands::add_key(&mut state, &config, path, name, !required),
+            KeysCommand::Remove { names } => commands::remove_keys(&mut state, &config, names),
+            KeysCommand::List => commands::list_keys(&state, &config),
+        },
+        SubCommand::Export { path, filter } => {
+            if !opt.no_scan {
+                state.scan(&config)?;
+            }
+            let filter = RepositoryFilter::new(&config, &filter)?;
+            commands::export(&state, &config, path, &filter)
+        }
+        SubCommand::Clone {
+            manifest,
+            with_hooks,
             not_parallel,
             threads,
         } => {
-            state.scan(&config)?;
-            load_keys(&config)?;
-            commands::update(&mut state, &config, all, !not_parallel, threads)
+            load_keys(&state.keys(&config))?;
+            commands::clone(
+                &mut state,
+                &config,
+                &manifest,
+                with_hooks,
+                !not_parallel,
+                threads,
+            )
         }
-        SubCommand::Check {
-            all,
-            not_parallel,
-            threads,
+        SubCommand::Exec {
+            filter,
+            finding,
+            args,
         } => {
-            state.scan(&config)?;
-            load_keys(&config)?;
-            commands::check(&mut state, &config, all, !not_parallel, threads)
+            if !opt.no_scan {
+                state.scan(&config)?;
+            }
+            load_keys(&state.keys(&config))?;
+            let filter = RepositoryFilter::new(&config, &filter)?.findings(finding);
+            commands::exec(&state, &config, &args, &filter)
+        }
+        SubCommand::Info { filter } => {
+            let filter = RepositoryFilter::new(&config, &filter)?;
+            commands::print_info(&config, &state, &filter)
+        }
+        SubCommand::Update { args, update } => {
+            if !opt.no_scan {
+                state.scan(&config)?;
+            }
+            load_keys(&state.keys(&config))?;
+            commands::update(&mut state, &config, &args, &update)
+        }
+        SubCommand::Prune {
+            filter,
+            dry_run,
+            yes,
+            force,
+            no_fetch,
+        } => {
+            if !opt.no_scan {
+                state.scan(&config)?;
+            }
+            if !no_fetch {
+                load_keys(&state.keys(&config))?;
+            }
+            let filter = RepositoryFilter::new(&config, &filter)?;
+            commands::prune(&state, &config, &filter, dry_run, yes, force, !no_fetch)
+        }
+        SubCommand::Check { args } => {
+            if !opt.no_scan {
+                state.scan(&config)?;
+            }
+            load_keys(&state.keys(&config))?;
+            commands::check(&mut state, &config, &args)
         }
     }
 }
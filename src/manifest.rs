@@ -0,0 +1,132 @@
+//! A manifest is a portable list of repositories, which can be used to set up a new machine.
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use shellexpand::tilde;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Manifest {
+    pub repositories: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ManifestEntry {
+    /// The path to the repository.
+    /// This is relative to `watched`, if the repository lives inside a watched directory.
+    pub path: PathBuf,
+    /// The watched directory this repository has been found in.
+    #[serde(default = "Default::default")]
+    pub watched: Option<PathBuf>,
+    /// All remotes of the repository and their urls.
+    pub remotes: BTreeMap<String, String>,
+    /// The default branch of the repository's remote.
+    #[serde(default = "Default::default")]
+    pub default_branch: Option<String>,
+    /// The command that's executed after a successful update.
+    #[serde(default = "Default::default")]
+    pub hook: Option<String>,
+}
+
+impl ManifestEntry {
+    /// The absolute path of the repository on this machine.
+    ///
+    /// Manifests might come from somewhere else, so repositories must never end up outside of
+    /// the directories that're watched on this machine. The entry's watched directory is
+    /// resolved to a locally watched one, see [local_watched], and its path has to be relative
+    /// to it. Entries without a watched directory need an absolute path inside of a locally
+    /// watched directory. Paths with `..` components are always rejected.
+    pub fn target_path(&self, watched: &[PathBuf]) -> Result<PathBuf> {
+        if self
+            .path
+            .components()
+            .any(|component| component == Component::ParentDir)
+        {
+            bail!("Path {:?} must not contain '..'", self.path);
+        }
+
+        let Some(entry_watched) = &self.watched else {
+            let path = PathBuf::from(tilde(&self.path.to_string_lossy()).into_owned());
+            if !path.is_absolute() {
+                bail!("Path {path:?} has to be absolute");
+            }
+            if !watched.iter().any(|watched| path.starts_with(watched)) {
+                bail!("Path {path:?} isn't inside of a watched directory");
+            }
+
+            return Ok(path);
+        };
+
+        let relative = !self.path.as_os_str().is_empty()
+            && self
+                .path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        if !relative {
+            bail!(
+                "Path {:?} has to be relative to its watched directory",
+                self.path
+            );
+        }
+
+        Ok(local_watched(entry_watched, watched)?.join(&self.path))
+    }
+}
+
+/// Find the directory that's watched on this machine for the watched directory of an entry.
+///
+/// That's the directory with the same path or, if there's none, the only one with the same
+/// name. This allows different directory layouts across machines, e.g. `~/projects` and
+/// `/srv/projects`.
+fn local_watched(entry_watched: &Path, watched: &[PathBuf]) -> Result<PathBuf> {
+    let entry_watched = PathBuf::from(tilde(&entry_watched.to_string_lossy()).into_owned());
+    if watched.contains(&entry_watched) {
+        return Ok(entry_watched);
+    }
+
+    let same_name: Vec<&PathBuf> = watched
+        .iter()
+        .filter(|watched| {
+            watched.file_name().is_some() && watched.file_name() == entry_watched.file_name()
+        })
+        .collect();
+    match same_name.as_slice() {
+        [watched] => Ok(watched.to_path_buf()),
+        [] => bail!("Neither {entry_watched:?} nor a directory with the same name is watched"),
+        _ => bail!("Multiple watched directories have the same name as {entry_watched:?}"),
+    }
+}
+
+impl Manifest {
+    /// Save the manifest to the given path.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = File::create(path)?;
+        serde_yaml::to_writer(file, &self).context("Failed to write manifest to disk:")?;
+
+        Ok(())
+    }
+
+    /// Load a manifest from the given path.
+    pub fn load(path: &Path) -> Result<Manifest> {
+        let file = File::open(path).context(format!("Couldn't open manifest at {path:?}"))?;
+        let manifest = serde_yaml::from_reader(file).context("Failed to parse manifest")?;
+
+        Ok(manifest)
+    }
+}
+
+/// Replace the home directory with `~`, so paths work across machines with different users.
+pub fn contract_home(path: &Path) -> PathBuf {
+    let Some(home) = dirs::home_dir() else {
+        return path.to_path_buf();
+    };
+
+    match path.strip_prefix(&home) {
+        Ok(relative) => PathBuf::from("~").join(relative),
+        Err(_) => path.to_path_buf(),
+    }
+}
@@ -1,36 +1,165 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fmt,
+    io::{BufRead, BufReader},
+    path::PathBuf,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
 
-use anyhow::{Result, bail};
-use subprocess::{Capture, Exec, Redirection};
+use anyhow::{Context, Result, bail};
+use signal_hook::{
+    consts::{SIGINT, SIGKILL, SIGTERM},
+    iterator::Signals,
+};
+use subprocess::{Capture, Exec, ExecExt, Job, Process, Redirection, unix::ProcessExt};
 
+/// Build a [Cmd] from a program and its arguments, e.g. `cmd!("git", "fetch", "--all")`.
 #[macro_export]
 macro_rules! cmd {
-    ($( $x:expr ),*) => {
-        Cmd::new(format!(
-            $($x,)*
-        ))
+    ($program:expr $(, $arg:expr)* $(,)?) => {
+        $crate::process::Cmd::new($program)$(.arg($arg))*
     };
 }
 
+/// The time a process gets to shut down gracefully, before it's killed.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// Whether the user requested to stop the current run.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether started commands may interact with the user via the terminal.
+static INTERACTIVE: AtomicBool = AtomicBool::new(true);
+
+/// All processes that're currently running, so they can be terminated on interrupts.
+/// The flag indicates whether the process has been started in its own process group.
+static RUNNING: Mutex<Vec<(Process, bool)>> = Mutex::new(Vec::new());
+
+/// The error that's returned, if a command didn't finish in time.
+#[derive(Debug)]
+pub struct Timeout {
+    pub command: String,
+    pub timeout: Duration,
+}
+
+impl fmt::Display for Timeout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Timed out after {}s: {}",
+            self.timeout.as_secs(),
+            self.command
+        )
+    }
+}
+
+impl std::error::Error for Timeout {}
+
+/// The error that's returned, if a command has been stopped by the user.
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Interrupted")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Terminate all running commands on Ctrl-C, instead of exiting right away.
+/// This allows us to still report the results of the current run.
+///
+/// A second Ctrl-C exits immediately.
+pub fn handle_interrupts() -> Result<()> {
+    let mut signals =
+        Signals::new([SIGINT, SIGTERM]).context("Failed to register signal handler")?;
+
+    thread::spawn(move || {
+        for _ in signals.forever() {
+            if INTERRUPTED.swap(true, Ordering::SeqCst) {
+                std::process::exit(130);
+            }
+
+            for (process, grouped) in RUNNING.lock().unwrap().iter() {
+                let _ = signal(process, *grouped, SIGTERM);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Whether the user requested to stop the current run.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Control whether started commands may interact with the user via the terminal,
+/// e.g. to ask for credentials.
+///
+/// Non-interactive commands don't get any stdin and run in their own process group.
+/// That way, they cannot read from the terminal and can be terminated including all of
+/// their children (e.g. `ssh`) on timeouts and interrupts.
+///
+/// Interactive commands have to stay in our process group, which is the foreground group of
+/// the terminal. The terminal stops processes of other groups as soon as they try to read from
+/// it, e.g. when `ssh` asks for a passphrase. Ctrl-C is sent to the whole foreground group,
+/// so their children are still stopped on interrupts, but timeouts only terminate the command
+/// itself.
+pub fn set_interactive(interactive: bool) {
+    INTERACTIVE.store(interactive, Ordering::SeqCst);
+}
+
 /// This is a convenience layer around [Subprocess's Exec](subprocess.Exec).
 /// It provides simple exit handling for single Commands.
-/// This doesn't work with pipes.
+///
+/// Programs are executed directly with their arguments, so arguments such as branch names or
+/// paths are never interpreted by a shell. Only user provided commands (e.g. hooks) should be
+/// run via [Cmd::shell].
 pub struct Cmd {
     cwd: Option<PathBuf>,
     pub env: HashMap<String, String>,
-    command: String,
+    program: OsString,
+    args: Vec<OsString>,
+    /// Whether `program` is a whole command line, which is interpreted by the shell.
+    shell: bool,
+    timeout: Option<Duration>,
 }
 
 impl Cmd {
-    /// Create a new wrapper with the command that should be executed.
-    pub fn new<T: ToString>(command: T) -> Cmd {
+    /// Create a new wrapper with the program that should be executed.
+    pub fn new(program: impl AsRef<OsStr>) -> Cmd {
         Cmd {
-            command: command.to_string(),
+            program: program.as_ref().to_os_string(),
+            args: Vec::new(),
+            shell: false,
             env: HashMap::new(),
             cwd: None,
+            timeout: None,
         }
     }
 
+    /// Create a new wrapper for a command line that's executed via the shell.
+    /// This allows pipes, variables and so on, e.g. `cargo build && cargo test`.
+    pub fn shell(command: impl AsRef<OsStr>) -> Cmd {
+        Cmd {
+            shell: true,
+            ..Cmd::new(command)
+        }
+    }
+
+    /// Add an argument, which is passed to the program as it is.
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Cmd {
+        self.args.push(arg.as_ref().to_os_string());
+        self
+    }
+
     /// Set the current working directory of the process.
     pub fn cwd(mut self, dir: PathBuf) -> Cmd {
         self.cwd = Some(dir);
@@ -38,39 +167,184 @@ impl Cmd {
         self
     }
 
-    /// Set the current working directory of the process.
+    /// Set the environment variables of the process.
     pub fn env(mut self, env: HashMap<String, String>) -> Cmd {
         self.env = env;
         self
     }
 
+    /// Terminate the process and all of its children, if it runs longer than the given timeout.
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Cmd {
+        self.timeout = timeout;
+        self
+    }
+
     /// Run the command and return the exit status
     pub fn run(&self) -> Result<Capture> {
-        let mut exec = Exec::shell(&self.command)
-            .stdout(Redirection::Pipe)
-            .stderr(Redirection::Merge);
+        let (mut job, timed_out, _registration) = self.start()?;
 
-        // Set the current working directory.
-        if let Some(cwd) = &self.cwd {
-            exec = exec.cwd(cwd);
+        let (stdout, stderr) = job
+            .communicate()
+            .and_then(|mut communicator| communicator.read())
+            .context(format!("Failed to read output of: {}", self.command_line()))?;
+
+        self.finish(job, &timed_out, stdout, stderr)
+    }
+
+    /// Run the command and call `on_line` for each line of output as soon as it's available.
+    /// The whole output is returned as well, once the command finished.
+    pub fn stream(&self, mut on_line: impl FnMut(&str)) -> Result<Capture> {
+        let (mut job, timed_out, _registration) = self.start()?;
+
+        let stdout = job.stdout.take().context("Expected stdout to be piped")?;
+        let mut reader = BufReader::new(stdout);
+        let mut output = Vec::new();
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line)? > 0 {
+            on_line(String::from_utf8_lossy(&line).trim_end());
+            output.append(&mut line);
         }
 
-        for (key, value) in self.env.iter() {
-            exec = exec.env(key, value);
+        self.finish(job, &timed_out, output, Vec::new())
+    }
+
+    /// Start the process and register it, so it can be terminated on interrupts.
+    /// The process stays registered until the returned [Registration] is dropped.
+    ///
+    /// If there's a timeout, a watchdog is started, which terminates the process once the
+    /// timeout is exceeded. The returned flag indicates whether this happened.
+    fn start(&self) -> Result<(Job, Arc<AtomicBool>, Registration)> {
+        if interrupted() {
+            return Err(Interrupted.into());
         }
 
-        // Check if there are any critical errors.
-        let capture_data = match exec.capture() {
-            Ok(exit_status) => exit_status,
+        let job = match self.exec().start() {
+            Ok(job) => job,
             Err(error) => {
                 bail!(
                     "Failed during: {} \nCritical error: {}",
-                    &self.command,
+                    self.command_line(),
                     error
                 );
             }
         };
 
-        Ok(capture_data)
+        let process = job.processes[0].clone();
+        let grouped = !INTERACTIVE.load(Ordering::SeqCst);
+        RUNNING.lock().unwrap().push((process.clone(), grouped));
+        let registration = Registration { pid: process.pid() };
+
+        let timed_out = Arc::new(AtomicBool::new(false));
+        if let Some(timeout) = self.timeout {
+            let timed_out = timed_out.clone();
+            thread::spawn(move || {
+                if let Ok(None) = process.wait_timeout(timeout) {
+                    timed_out.store(true, Ordering::SeqCst);
+                    terminate(&process, grouped);
+                }
+            });
+        }
+
+        Ok((job, timed_out, registration))
+    }
+
+    /// Wait for the process to exit.
+    fn finish(
+        &self,
+        job: Job,
+        timed_out: &AtomicBool,
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+    ) -> Result<Capture> {
+        let exit_status = job.wait()?;
+
+        if timed_out.load(Ordering::SeqCst) {
+            return Err(Timeout {
+                command: self.command_line(),
+                timeout: self.timeout.unwrap_or_default(),
+            }
+            .into());
+        }
+
+        // The process has most likely been terminated by us.
+        if interrupted() && !exit_status.success() {
+            return Err(Interrupted.into());
+        }
+
+        Ok(Capture {
+            stdout,
+            stderr,
+            exit_status,
+        })
+    }
+
+    /// A human-readable representation of the command for error messages.
+    fn command_line(&self) -> String {
+        let mut parts = vec![self.program.to_string_lossy()];
+        parts.extend(self.args.iter().map(|arg| arg.to_string_lossy()));
+
+        parts.join(" ")
+    }
+
+    /// Build the actual [Exec] for this command.
+    fn exec(&self) -> Exec {
+        let exec = if self.shell {
+            Exec::shell(&self.program)
+        } else {
+            Exec::cmd(&self.program).args(&self.args)
+        };
+
+        let mut exec = exec.stdout(Redirection::Pipe).stderr(Redirection::Merge);
+
+        // Interactive commands must stay in the terminal's foreground group, see
+        // [set_interactive].
+        if !INTERACTIVE.load(Ordering::SeqCst) {
+            exec = exec.stdin(Redirection::Null).setpgid();
+        }
+
+        // Set the current working directory.
+        if let Some(cwd) = &self.cwd {
+            exec = exec.cwd(cwd);
+        }
+
+        for (key, value) in self.env.iter() {
+            exec = exec.env(key, value);
+        }
+
+        exec
+    }
+}
+
+/// Removes a process from the list of running processes, once it's dropped.
+///
+/// This happens on every exit path of a command, including errors. Otherwise, interrupts would
+/// signal a process that doesn't exist anymore or whose pid has been reused.
+struct Registration {
+    pid: u32,
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        RUNNING
+            .lock()
+            .unwrap()
+            .retain(|(process, _)| process.pid() != self.pid);
+    }
+}
+
+/// Send a signal to the process or its whole process group.
+fn signal(process: &Process, grouped: bool, signal: i32) -> std::io::Result<()> {
+    if grouped {
+        process.send_signal_group(signal)
+    } else {
+        process.send_signal(signal)
+    }
+}
+
+/// Give the process a chance to shut down gracefully, before killing it.
+fn terminate(process: &Process, grouped: bool) {
+    let _ = signal(process, grouped, SIGTERM);
+    if let Ok(None) = process.wait_timeout(SHUTDOWN_GRACE_PERIOD) {
+        let _ = signal(process, grouped, SIGKILL);
     }
 }
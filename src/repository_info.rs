@@ -1,42 +1,178 @@
-use std::path::PathBuf;
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
+use clap::ValueEnum;
+use serde::Serialize;
 use strum::Display;
 
-use crate::config::Hook;
+use crate::{
+    config::{Hook, RepositorySettings, Strategy, Timeouts},
+    git::AuthenticationRequired,
+    process::{Interrupted, Timeout},
+};
 
-#[derive(Display)]
-pub enum RepositoryState {
+/// The outcome of handling a repository.
+#[derive(Display, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateOutcome {
+    /// The repository hasn't been (fully) handled.
     Unknown,
-    /// The current git HEAD is detached.
-    Detached,
+    /// The repo has only been checked during a `Check` run.
+    Checked,
     /// Repo has been fetched, merged and is up-to-date.
     UpToDate,
-    /// The repo looks fine during a `Check` run.
-    Ok,
-    /// We just fetched the newest info from the default remote.
+    /// We fetched new changes from the remote, but couldn't update the current branch.
     Fetched,
     /// The repository has been successfully updated.
     Updated,
-    /// There's no way to fast-forward merge.
+    /// The repository has been updated, local changes have been stashed and restored.
+    UpdatedWithStash,
+    /// The update caused conflicts and the repository has been restored to its previous state.
+    RolledBack,
+}
+
+/// The outcome of updating a local branch, which isn't checked out.
+#[derive(Display, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BranchOutcome {
+    /// The branch already contains all commits of its upstream.
+    UpToDate,
+    /// The branch has been fast-forwarded to its upstream.
+    Updated,
+    /// The branch and its upstream diverged.
     NoFastForward,
+}
+
+/// A local branch, which has been updated alongside the checked out one.
+#[derive(Serialize)]
+pub struct BranchUpdate {
+    pub name: String,
+    /// The full name of the ref the branch has been updated from.
+    pub upstream: String,
+    pub outcome: BranchOutcome,
+}
+
+/// Something noteworthy about a repository.
+/// A repository can have any number of findings at the same time.
+#[derive(Display, Serialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum Finding {
     /// There're some local filesystem changes.
     LocalChanges,
+    /// There're stashed entries in this repo.
+    Stashed,
     /// There're unpushed commits in this repo.
     NotPushed,
+    /// The upstream has commits that aren't in the current branch yet.
+    Behind,
+    /// The current git HEAD is detached.
+    Detached,
+    /// There's no way to fast-forward merge.
+    NoFastForward,
+    /// The current branch has no upstream to compare against or update from.
+    NoUpstream,
+    /// Updating caused conflicts, so the update has been rolled back.
+    UpdateConflict,
+    /// The checked out branch isn't the default branch of its remote.
+    NotOnDefaultBranch,
+    /// The checked out branch has been merged into the default branch.
+    Merged,
+    /// A merged branch was checked out, so geil switched to the default branch.
+    /// The previous branch can be found in [RepositoryInfo::switched_from].
+    Switched,
+    /// Something went wrong while handling this repository.
+    /// The error message can be found in [RepositoryInfo::error].
+    Error,
+    /// Handling this repository took longer than the configured timeout.
+    TimedOut,
+    /// The run has been interrupted before this repository could be fully handled.
+    Interrupted,
+    /// Git needs credentials or a host key confirmation, but wasn't allowed to ask for them.
+    AuthenticationRequired,
+}
+
+/// The amount of changed files in the working tree, by type.
+#[derive(Default, Serialize)]
+pub struct FileChanges {
+    /// Files with changes that're staged for the next commit.
+    pub staged: usize,
+    /// Tracked files with changes that aren't staged yet.
+    pub modified: usize,
+    /// Files that aren't tracked by git.
+    pub untracked: usize,
+    /// Files with unresolved merge conflicts.
+    pub conflicted: usize,
+}
+
+impl FileChanges {
+    /// Whether there're any changes at all.
+    /// Untracked files can be explicitly ignored.
+    pub fn is_clean(&self, ignore_untracked: bool) -> bool {
+        self.staged == 0
+            && self.modified == 0
+            && self.conflicted == 0
+            && (ignore_untracked || self.untracked == 0)
+    }
 }
 
 pub struct RepositoryInfo {
     pub path: PathBuf,
     pub name: String,
-    pub state: RepositoryState,
+    pub outcome: UpdateOutcome,
+    /// Everything noteworthy we found out about this repository.
+    pub findings: BTreeSet<Finding>,
     pub stashed: usize,
+    /// The local changes in the working tree.
+    pub changes: FileChanges,
+    /// The amount of commits the current branch is ahead of its upstream.
+    /// `None` if the branch has no upstream.
+    pub ahead: Option<usize>,
+    /// The amount of commits the current branch is behind its upstream.
+    /// `None` if the branch has no upstream.
+    pub behind: Option<usize>,
+    /// The default branch of the remote, if it's known.
+    pub default_branch: Option<String>,
+    /// The amount of commits the current branch is behind the default branch.
+    /// `None` if the default branch is checked out or unknown.
+    pub behind_default: Option<usize>,
+    /// The merged branch that was checked out before switching to the default branch.
+    pub switched_from: Option<String>,
+    /// The outcomes for all other local branches with an upstream.
+    /// Only contains entries, if all branches are updated.
+    pub branches: Vec<BranchUpdate>,
     /// The time (ms) it took to check the repo.
     pub check_time: Option<usize>,
     pub hook: Option<String>,
+    /// The remote to compare against, instead of the configured upstream.
+    pub remote: Option<String>,
+    /// Whether all local branches with an upstream should be updated.
+    pub all_branches: bool,
+    /// How the checked out branch is updated.
+    pub strategy: Strategy,
+    /// Whether the default branch should be checked out instead of a merged branch.
+    pub switch_merged: bool,
+    /// Whether remote refs, which no longer exist on the remote, are deleted while fetching.
+    pub prune: bool,
+    /// The groups this repository belongs to.
+    pub groups: Vec<String>,
+    /// The error that occurred while handling this repository.
+    pub error: Option<String>,
+    /// The maximum time a single command may take.
+    command_timeout: Option<Duration>,
+    /// The point in time at which all commands for this repository have to be done.
+    deadline: Option<Instant>,
 }
 
 impl RepositoryInfo {
-    pub fn new(path: PathBuf, hook: Option<&Hook>) -> RepositoryInfo {
+    pub fn new(
+        path: PathBuf,
+        hook: Option<&Hook>,
+        settings: Option<&RepositorySettings>,
+        groups: Vec<String>,
+    ) -> RepositoryInfo {
         // Get the repository name from the path for the progress bar
         let name = path.file_name().map_or("no_name?".to_string(), |name| {
             name.to_string_lossy().to_string()
@@ -45,10 +181,72 @@ impl RepositoryInfo {
         RepositoryInfo {
             path,
             name,
-            state: RepositoryState::Unknown,
+            outcome: UpdateOutcome::Unknown,
+            findings: BTreeSet::new(),
             stashed: 0,
+            changes: FileChanges::default(),
+            ahead: None,
+            behind: None,
+            default_branch: None,
+            behind_default: None,
+            switched_from: None,
+            branches: Vec::new(),
             check_time: None,
             hook: hook.map(|hook| hook.command.clone()),
+            remote: settings.and_then(|settings| settings.remote.clone()),
+            all_branches: false,
+            strategy: Strategy::default(),
+            switch_merged: false,
+            prune: false,
+            groups,
+            error: None,
+            command_timeout: None,
+            deadline: None,
+        }
+    }
+
+    /// Start the timeouts for handling this repository.
+    pub fn start_timeouts(&mut self, timeouts: &Timeouts) {
+        self.command_timeout = timeouts.command();
+        self.deadline = timeouts
+            .repository()
+            .map(|timeout| Instant::now() + timeout);
+    }
+
+    /// The time the next command for this repository may take.
+    /// This respects the command timeout as well as the time left for the whole repository.
+    pub fn timeout(&self) -> Option<Duration> {
+        let remaining = self
+            .deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()));
+
+        match (self.command_timeout, remaining) {
+            (Some(command), Some(remaining)) => Some(command.min(remaining)),
+            (command, remaining) => command.or(remaining),
+        }
+    }
+
+    /// Record a finding about this repository.
+    pub fn add(&mut self, finding: Finding) {
+        self.findings.insert(finding);
+    }
+
+    /// Check whether something specific has been found for this repository.
+    pub fn has(&self, finding: Finding) -> bool {
+        self.findings.contains(&finding)
+    }
+
+    /// Record an error that occurred while handling this repository.
+    pub fn set_error(&mut self, error: anyhow::Error) {
+        if error.is::<Interrupted>() {
+            self.add(Finding::Interrupted);
+        } else if error.is::<Timeout>() {
+            self.add(Finding::TimedOut);
+        } else if error.is::<AuthenticationRequired>() {
+            self.add(Finding::AuthenticationRequired);
+        } else {
+            self.add(Finding::Error);
         }
+        self.error = Some(format!("{error:#}"));
     }
 }
@@ -1,44 +1,40 @@
 //! This module handles all ssh key related logic.
 use std::{
     fs::read_to_string,
+    path::{Path, PathBuf},
     process::{Command, Stdio},
 };
 
 use anyhow::{Context, Result, bail};
 
-use crate::config::GeilConfig;
+use crate::config::SshKey;
 
 /// Pre-load all keys that have been added by the user.
-pub fn load_keys(config: &GeilConfig) -> Result<()> {
-    if config.keys.is_empty() {
+pub fn load_keys(keys: &[SshKey]) -> Result<()> {
+    if keys.is_empty() {
         return Ok(());
     }
 
     // Get the list of keys that're already added to ssh-agent.
-    let output = Command::new("ssh-add")
-        .arg("-L")
-        .output()
-        .context("Failed to get list of already added keys.")?;
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let known_keys: Vec<&str> = stdout.lines().collect();
+    let known_keys = loaded_keys()?;
 
     // Add all keys that aren't in there.
-    for key in config.keys.iter() {
-        let mut pub_key_path = key.path();
-        pub_key_path.set_extension("pub");
+    for key in keys.iter() {
+        let pub_key_path = public_key_path(&key.path());
 
         if !pub_key_path.exists() {
+            // Optional keys don't need to exist on every machine.
+            if key.optional {
+                continue;
+            }
             bail!(
                 "Couldn't find public key for key '{}' at path {pub_key_path:?}",
                 key.name
             )
         }
 
-        // Read the public key.
-        let pub_key = read_to_string(pub_key_path).context("Couldn't read public key file.")?;
-
         // The key is already added, check the next one.
-        if known_keys.contains(&pub_key.trim()) {
+        if is_loaded(key, &known_keys)? {
             continue;
         }
 
@@ -53,3 +49,83 @@ pub fn load_keys(config: &GeilConfig) -> Result<()> {
 
     Ok(())
 }
+
+/// Get the fingerprints of all keys that're currently added to ssh-agent.
+pub fn loaded_keys() -> Result<Vec<String>> {
+    let output = Command::new("ssh-add")
+        .arg("-l")
+        .output()
+        .context("Failed to get list of already added keys.")?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Each line looks like this: `256 SHA256:$hash comment (ED25519)`
+    // The comment of a loaded key might differ from the one in the public key file,
+    // which is why only the fingerprints are compared.
+    Ok(stdout
+        .lines()
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(|fingerprint| fingerprint.to_string())
+        .collect())
+}
+
+/// Check whether the given key is part of the list of loaded key fingerprints.
+pub fn is_loaded(key: &SshKey, known_keys: &[String]) -> Result<bool> {
+    let fingerprint = fingerprint(&key.path())?;
+
+    Ok(known_keys.contains(&fingerprint))
+}
+
+/// The public key is expected to be next to the private key with a `.pub` extension.
+pub fn public_key_path(private_key_path: &Path) -> PathBuf {
+    let mut pub_key_path = private_key_path.to_path_buf();
+    pub_key_path.set_extension("pub");
+
+    pub_key_path
+}
+
+/// Make sure that a private key and its public key exist at the given path.
+pub fn validate_key_pair(private_key_path: &Path) -> Result<()> {
+    if !private_key_path.is_file() {
+        bail!("Couldn't find private key at path {private_key_path:?}");
+    }
+
+    let private_key =
+        read_to_string(private_key_path).context("Couldn't read private key file.")?;
+    if !private_key.contains("PRIVATE KEY") {
+        bail!("File at {private_key_path:?} doesn't look like a private key");
+    }
+
+    let pub_key_path = public_key_path(private_key_path);
+    if !pub_key_path.is_file() {
+        bail!("Couldn't find public key at path {pub_key_path:?}");
+    }
+
+    // Let ssh-keygen check that the public key is actually valid.
+    fingerprint(private_key_path)?;
+
+    Ok(())
+}
+
+/// Get the fingerprint of a key via its public key.
+pub fn fingerprint(private_key_path: &Path) -> Result<String> {
+    let pub_key_path = public_key_path(private_key_path);
+    let output = Command::new("ssh-keygen")
+        .arg("-l")
+        .arg("-f")
+        .arg(&pub_key_path)
+        .output()
+        .context("Failed to call ssh-keygen.")?;
+
+    if !output.status.success() {
+        bail!("Invalid public key at {pub_key_path:?}");
+    }
+
+    // The output looks like this: `256 SHA256:$hash comment (ED25519)`
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let fingerprint = stdout
+        .split_whitespace()
+        .nth(1)
+        .context("Couldn't read fingerprint from ssh-keygen output")?;
+
+    Ok(fingerprint.to_string())
+}
@@ -1,14 +1,20 @@
 use std::{
-    fs::{File, read_dir},
+    collections::HashSet,
+    fs::File,
     path::{Path, PathBuf},
 };
 
 use anyhow::{Context, Result, anyhow};
-use log::{debug, warn};
+use log::warn;
 use serde::{Deserialize, Serialize};
 use serde_with::{DefaultOnError, serde_as};
 
-use crate::{config::GeilConfig, repository_info::RepositoryInfo};
+use crate::{
+    config::{GeilConfig, Hook, SshKey, WatchedDirectory},
+    discovery::{DirectoryCache, Ignored, discover, real_path},
+    filter::RepositoryFilter,
+    repository_info::{Finding, RepositoryInfo},
+};
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Repository {
@@ -16,6 +22,11 @@ pub struct Repository {
     pub path: PathBuf,
     /// The time it took to check this repository in the last run.
     pub check_time: Option<usize>,
+    /// Whether the repository has been explicitly added via `geil add`.
+    /// Such repositories are kept, even if they're inside of a directory that's no longer
+    /// watched.
+    #[serde(default = "Default::default")]
+    pub added: bool,
 }
 
 impl Repository {
@@ -23,6 +34,7 @@ impl Repository {
         Self {
             path,
             check_time: None,
+            added: false,
         }
     }
 }
@@ -30,29 +42,62 @@ impl Repository {
 #[serde_as]
 #[derive(Deserialize, Serialize)]
 pub struct State {
+    /// All local-machine-only paths that're actively watched for new repositories.
+    #[serde(default = "Default::default")]
+    pub watched: Vec<WatchedDirectory>,
     /// All local-machine-only paths that're explicitly ignored.
+    /// In contrast to the config, these are literal paths and never globs.
     #[serde(default = "Default::default")]
     pub ignored: Vec<PathBuf>,
+    /// Ssh keys that have been added via `geil keys add`.
+    /// They're stored in here, as the user's config file is never rewritten.
+    #[serde(default = "Default::default")]
+    pub keys: Vec<SshKey>,
+    /// Hooks of repositories that have been cloned from a manifest.
+    #[serde(default = "Default::default")]
+    pub hooks: Vec<Hook>,
     /// Infos about previous repos and their execution time.
     #[serde_as(deserialize_as = "DefaultOnError")]
     pub repositories: Vec<Repository>,
+    /// The modification times of all directories that have been found during discovery.
+    /// Unchanged directories don't need to be read again.
+    #[serde(default = "Default::default")]
+    pub discovery_cache: DirectoryCache,
+    /// The file this state has been loaded from.
+    /// The default location is used, if it isn't set.
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl State {
     pub fn new() -> State {
         State {
+            watched: Vec::new(),
             ignored: Vec::new(),
+            keys: Vec::new(),
+            hooks: Vec::new(),
             repositories: Vec::new(),
+            discovery_cache: DirectoryCache::default(),
+            path: None,
         }
     }
 }
 
 impl State {
-    /// Save a state to the disk.
+    /// Save the state to the file it has been loaded from.
     pub fn save(&mut self) -> Result<()> {
         self.repositories.sort_by(|a, b| a.path.cmp(&b.path));
-        let path = default_cache_path()?;
-        let file = File::create(path)?;
+        let path = match &self.path {
+            Some(path) => path.clone(),
+            None => default_cache_path()?,
+        };
+        let file = File::create(&path).context(format!("Failed to create state file {path:?}"))?;
 
         serde_yaml::to_writer(file, &self).context("Failed to write state to disk:")?;
 
@@ -60,19 +105,29 @@ impl State {
     }
 
     /// Load an existing state from the disk or create an empty new one.
-    pub fn load() -> Result<State> {
-        let path = default_cache_path()?;
-        // Return default path if it doesn't exist yet
+    /// If no path is given, the state is expected at the default location.
+    pub fn load(path: Option<PathBuf>) -> Result<State> {
+        let path = match path {
+            Some(path) => path,
+            None => default_cache_path()?,
+        };
+        // Start with an empty state, if it doesn't exist yet.
         if !path.exists() {
-            return Ok(State::new());
+            return Ok(State {
+                path: Some(path),
+                ..State::new()
+            });
         }
 
-        let file = File::open(path)?;
-        let state = serde_yaml::from_reader(file)?;
+        let file = File::open(&path)?;
+        let mut state: State = serde_yaml::from_reader(file)?;
+        state.path = Some(path);
 
         Ok(state)
     }
 
+    /// Forget repositories that no longer exist or are ignored and discover new repositories
+    /// in all watched directories.
     pub fn scan(&mut self, config: &GeilConfig) -> Result<()> {
         // Go through all cached repositories and check if they still exist
         for key in (0..self.repositories.len()).rev() {
@@ -81,7 +136,7 @@ impl State {
                 || !self.repositories[key].path.join(".git").exists()
                 || !self.repositories[key].path.join(".git").is_dir()
             {
-                println!(
+                eprintln!(
                     "Repository does no longer exist: {:?}",
                     &self.repositories[key].path
                 );
@@ -89,20 +144,31 @@ impl State {
             }
         }
 
-        // Merge both, local ignored paths and ignored paths from config
-        let mut ignored_paths: Vec<PathBuf> = config.ignored().collect();
-        ignored_paths.append(&mut self.ignored.clone());
-
-        // Do a full repository discovery on all watched repositories
-        for watched in config.watched() {
-            let mut new_repos = Vec::new();
-            discover(&ignored_paths, &watched, 0, &mut new_repos);
-            for repo in new_repos {
-                if !self.has_repo_at_path(&repo.path) {
-                    println!("Found new repository: {:?}", repo.path);
-                    self.repositories.push(repo);
-                }
+        // Forget repositories that have been ignored since they've been discovered.
+        // Explicitly added repositories are always kept.
+        let ignored = self.ignored(config)?;
+        let explicit_repos: Vec<PathBuf> = config.repositories().collect();
+        self.repositories.retain(|repo| {
+            if repo.added || explicit_repos.contains(&repo.path) || !ignored.contains(&repo.path) {
+                return true;
             }
+
+            eprintln!("Repository is ignored: {:?}", repo.path);
+            false
+        });
+
+        // Do a repository discovery on all watched directories.
+        // The new cache only contains the directories that're still being watched.
+        let (repositories, cache) = discover(
+            &self.watched_directories(config),
+            &config.discovery,
+            &ignored,
+            &self.discovery_cache,
+        )?;
+        self.discovery_cache = cache;
+        for repo in self.unknown_repositories(repositories) {
+            eprintln!("Found new repository: {:?}", repo.path);
+            self.repositories.push(repo);
         }
 
         // Check all explicitly added single repos.
@@ -112,10 +178,7 @@ impl State {
                 continue;
             }
             if !self.has_repo_at_path(&repo_path) {
-                self.repositories.push(Repository {
-                    path: repo_path,
-                    check_time: None,
-                });
+                self.repositories.push(Repository::new(repo_path));
             }
         }
 
@@ -124,26 +187,93 @@ impl State {
         Ok(())
     }
 
+    /// Merge both, local watched directories and watched directories from config.
+    pub fn watched_directories(&self, config: &GeilConfig) -> Vec<WatchedDirectory> {
+        let mut watched = config.watched.clone();
+        watched.append(&mut self.watched.clone());
+
+        watched
+    }
+
+    /// The paths of all local and configured watched directories.
+    pub fn watched_paths(&self, config: &GeilConfig) -> Vec<PathBuf> {
+        self.watched_directories(config)
+            .iter()
+            .map(WatchedDirectory::path)
+            .collect()
+    }
+
+    /// Build the matcher for all configured ignore patterns and locally ignored paths.
+    /// Local paths come from `geil ignore` and are never interpreted as globs.
+    pub fn ignored(&self, config: &GeilConfig) -> Result<Ignored> {
+        Ignored::new(config.ignored(), self.ignored.iter().cloned())
+    }
+
+    /// Merge both, local keys and keys from config.
+    pub fn keys(&self, config: &GeilConfig) -> Vec<SshKey> {
+        let mut keys = config.keys.clone();
+        keys.append(&mut self.keys.clone());
+
+        keys
+    }
+
+    /// Merge both, local hooks and hooks from config.
+    /// Hooks from the config take precedence, if there're multiple for the same repository.
+    pub fn hooks(&self, config: &GeilConfig) -> Vec<Hook> {
+        let mut hooks = config.hooks.clone();
+        hooks.append(&mut self.hooks.clone());
+
+        hooks
+    }
+
     pub fn has_repo_at_path(&self, path: &Path) -> bool {
         self.repositories.iter().any(|repo| repo.path == path)
     }
 
+    /// Filter all discovered repositories that are already known, possibly by another path.
+    /// Repositories are compared by their real path, as they can be reached via symlinks.
+    pub fn unknown_repositories(&self, repositories: Vec<Repository>) -> Vec<Repository> {
+        let known: HashSet<PathBuf> = self
+            .repositories
+            .iter()
+            .map(|repo| real_path(&repo.path))
+            .collect();
+
+        repositories
+            .into_iter()
+            .filter(|repo| !known.contains(&real_path(&repo.path)))
+            .collect()
+    }
+
     /// Create a list of [RepositoryInfo]s for internal processing, based on the list
     /// of known Git repositories.
     ///
+    /// Only repositories that pass the given filter are included.
+    ///
     /// Order the repositories by check wall time from the last run.
     /// Repositories with long running checks will be at the top of the vector.
     /// That way, we try to minimize wall execution time, by doing smarter scheduling.
-    pub fn repo_infos_by_wall_time(&self, config: &GeilConfig) -> Vec<RepositoryInfo> {
+    pub fn repo_infos_by_wall_time(
+        &self,
+        config: &GeilConfig,
+        filter: &RepositoryFilter,
+    ) -> Vec<RepositoryInfo> {
         let mut repos = self.repositories.clone();
         repos.sort_by_key(|b| std::cmp::Reverse(b.check_time));
 
         // We create a struct for our internal representation for each repository
+        let hooks = self.hooks(config);
         let mut repo_infos: Vec<RepositoryInfo> = Vec::new();
         for repo in repos {
-            let hook = config.hooks.iter().find(|hook| hook.path() == repo.path);
+            let groups = config.groups_of(&repo.path);
+            if !filter.matches(&repo.path, &groups) {
+                continue;
+            }
 
-            let repository_info = RepositoryInfo::new(repo.path.clone(), hook);
+            let hook = hooks.iter().find(|hook| hook.path() == repo.path);
+            let settings = config.settings(&repo.path);
+
+            let repository_info = RepositoryInfo::new(repo.path.clone(), hook, settings, groups);
             repo_infos.push(repository_info);
         }
 
@@ -158,7 +288,10 @@ impl State {
                 .find(|r| r.path == info.path)
                 .context("Expect repository to be there")?;
 
-            repo.check_time = info.check_time;
+            // Interrupted runs don't tell us anything about the usual check time.
+            if !info.has(Finding::Interrupted) {
+                repo.check_time = info.check_time;
+            }
         }
         self.save()?;
 
@@ -166,61 +299,6 @@ impl State {
     }
 }
 
-/// Discover repositories inside a given folder.
-pub fn discover(
-    ignored_paths: &[PathBuf],
-    path: &Path,
-    depths: usize,
-    new_repos: &mut Vec<Repository>,
-) {
-    if ignored_paths.contains(&path.to_path_buf()) {
-        return;
-    }
-
-    // Check if a .git directory exists.
-    // If it does, always stop searching.
-    let git_dir = path.join(".git");
-    debug!("{depths} Looking at folder {path:?}");
-    if git_dir.exists() {
-        debug!("Found .git folder");
-        // Add the repository, if we don't know it yet.
-        new_repos.push(Repository::new(path.to_owned()));
-        return;
-    }
-
-    // Recursion stop. Only check up to a dephts of 5
-    if depths == 5 {
-        debug!("Max depth reached");
-        return;
-    }
-
-    let current_dir = match read_dir(path) {
-        Ok(current_dir) => current_dir,
-        Err(err) => {
-            debug!("Couldn't read directory at {path:?} with error: {err:?}");
-            return;
-        }
-    };
-
-    // The current path is no repository, search it's subdirectories
-    for entry_result in current_dir {
-        match entry_result {
-            Ok(entry) => {
-                let path = entry.path();
-                if !path.is_dir() {
-                    continue;
-                }
-
-                discover(ignored_paths, &path, depths + 1, new_repos);
-            }
-            Err(err) => {
-                debug!("Couldn't read directory path {path:?} with error: {err:?}");
-                continue;
-            }
-        }
-    }
-}
-
 fn default_cache_path() -> Result<PathBuf> {
     let home = dirs::state_dir().ok_or_else(|| anyhow!("Couldn't resolve home dir"))?;
     let path = home.join("geil.state");
@@ -0,0 +1,272 @@
+//! Discover repositories in watched directories.
+use std::{
+    fs::{self, File},
+    os::unix::fs::symlink,
+    path::{Path, PathBuf},
+};
+
+use geil::{
+    config::{Discovery, GeilConfig, Symlinks, WatchedDirectory},
+    discovery::{DirectoryCache, Ignored, discover},
+    state::State,
+};
+use tempfile::TempDir;
+
+/// Create a fake repository, which is enough for discovery.
+fn repository(path: &Path) {
+    fs::create_dir_all(path.join(".git")).unwrap();
+}
+
+/// Discover all repositories in the directory and return their paths relative to it.
+fn discovered(watched: &WatchedDirectory, patterns: &[&str]) -> Vec<PathBuf> {
+    let ignored = Ignored::new(patterns.iter().map(PathBuf::from), []).expect("Invalid patterns");
+    discovered_with(watched, &ignored)
+}
+
+/// Discover all repositories in the directory, which aren't ignored.
+fn discovered_with(watched: &WatchedDirectory, ignored: &Ignored) -> Vec<PathBuf> {
+    let (repositories, _) = discover(
+        std::slice::from_ref(watched),
+        &Discovery::default(),
+        ignored,
+        &DirectoryCache::default(),
+    )
+    .unwrap();
+
+    relative(
+        repositories.into_iter().map(|repo| repo.path),
+        &watched.path(),
+    )
+}
+
+/// Get the sorted paths relative to the root directory.
+fn relative(paths: impl Iterator<Item = PathBuf>, root: &Path) -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = paths
+        .map(|path| path.strip_prefix(root).unwrap().to_path_buf())
+        .collect();
+    paths.sort();
+
+    paths
+}
+
+fn watched(dir: &TempDir) -> WatchedDirectory {
+    WatchedDirectory::new(dir.path().to_path_buf())
+}
+
+#[test]
+fn max_depth() {
+    let dir = TempDir::new().unwrap();
+    repository(&dir.path().join("a"));
+    repository(&dir.path().join("1/2/b"));
+    repository(&dir.path().join("1/2/3/4/5/6/c"));
+
+    let mut watched = watched(&dir);
+    assert_eq!(
+        discovered(&watched, &[]),
+        vec![PathBuf::from("1/2/b"), PathBuf::from("a")]
+    );
+
+    watched.max_depth = Some(1);
+    assert_eq!(discovered(&watched, &[]), vec![PathBuf::from("a")]);
+
+    watched.max_depth = Some(7);
+    assert_eq!(discovered(&watched, &[]).len(), 3);
+}
+
+#[test]
+fn ignored_globs() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    repository(&root.join("app"));
+    repository(&root.join("web/node_modules/dependency"));
+    repository(&root.join("go/vendor/dependency"));
+    repository(&root.join("old/project"));
+
+    let old = root.join("old");
+    let patterns = ["node_modules", "**/vendor/", old.to_str().unwrap()];
+    assert_eq!(
+        discovered(&watched(&dir), &patterns),
+        vec![PathBuf::from("app")]
+    );
+}
+
+#[test]
+fn ignored_paths_are_literal() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    for name in ["proj[1]", "proj1", "a*", "ab"] {
+        repository(&root.join(name));
+    }
+
+    // As globs, these would ignore `proj1` and `ab` instead.
+    let ignored = Ignored::new([], [root.join("proj[1]"), root.join("a*")]).unwrap();
+    assert_eq!(
+        discovered_with(&watched(&dir), &ignored),
+        vec![PathBuf::from("ab"), PathBuf::from("proj1")]
+    );
+}
+
+#[test]
+fn symlinks() {
+    let dir = TempDir::new().unwrap();
+    let other = TempDir::new().unwrap();
+    repository(&dir.path().join("a"));
+    repository(&other.path().join("b"));
+    symlink(other.path(), dir.path().join("linked")).unwrap();
+    // A symlink loop, which must not be followed forever.
+    symlink(dir.path(), dir.path().join("loop")).unwrap();
+
+    let mut watched = watched(&dir);
+    watched.max_depth = Some(20);
+
+    // Repositories keep the path they've been found by.
+    assert_eq!(
+        discovered(&watched, &[]),
+        vec![PathBuf::from("a"), PathBuf::from("linked/b")]
+    );
+
+    watched.symlinks = Some(Symlinks::Skip);
+    assert_eq!(discovered(&watched, &[]), vec![PathBuf::from("a")]);
+}
+
+#[test]
+fn cache() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    repository(&root.join("a"));
+    repository(&root.join("1/2/b"));
+
+    let watched = [watched(&dir)];
+    let ignored = Ignored::new([], []).unwrap();
+    let discover = |cache: &DirectoryCache| {
+        let (repositories, cache) =
+            discover(&watched, &Discovery::default(), &ignored, cache).unwrap();
+        (
+            relative(repositories.into_iter().map(|repo| repo.path), root),
+            cache,
+        )
+    };
+
+    let (paths, cache) = discover(&DirectoryCache::default());
+    assert_eq!(paths, vec![PathBuf::from("1/2/b"), PathBuf::from("a")]);
+    assert!(!cache.is_empty());
+
+    // Unchanged directories are taken from the cache, which survives a round trip to the disk.
+    let cache: DirectoryCache =
+        serde_yaml::from_str(&serde_yaml::to_string(&cache).unwrap()).unwrap();
+    let (cached_paths, cache) = discover(&cache);
+    assert_eq!(cached_paths, paths);
+
+    // A repository that's added without changing the modification time of its parent isn't
+    // noticed, as the parent isn't read again.
+    let modified = fs::metadata(root).unwrap().modified().unwrap();
+    repository(&root.join("hidden"));
+    File::open(root).unwrap().set_modified(modified).unwrap();
+    let (cached_paths, cache) = discover(&cache);
+    assert_eq!(cached_paths, paths);
+    let (uncached_paths, _) = discover(&DirectoryCache::default());
+    assert_eq!(uncached_paths.len(), 3);
+
+    // New and removed repositories are still noticed.
+    repository(&root.join("1/2/c"));
+    fs::remove_dir_all(root.join("a")).unwrap();
+    let (paths, _) = discover(&cache);
+    assert_eq!(
+        paths,
+        vec![
+            PathBuf::from("1/2/b"),
+            PathBuf::from("1/2/c"),
+            PathBuf::from("hidden")
+        ]
+    );
+}
+
+#[test]
+fn cache_keeps_unsearched_directories() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    repository(&root.join("vendor/dependency"));
+    let watched = [watched(&dir)];
+    let discover = |patterns: &[&str], cache: &DirectoryCache| {
+        let ignored = Ignored::new(patterns.iter().map(PathBuf::from), []).unwrap();
+        let (repositories, cache) =
+            discover(&watched, &Discovery::default(), &ignored, cache).unwrap();
+        (
+            relative(repositories.into_iter().map(|repo| repo.path), root),
+            cache,
+        )
+    };
+
+    // Ignored directories are still cached, so they're found once they're no longer ignored.
+    let (paths, cache) = discover(&["vendor"], &DirectoryCache::default());
+    assert!(paths.is_empty());
+    let (paths, _) = discover(&[], &cache);
+    assert_eq!(paths, vec![PathBuf::from("vendor/dependency")]);
+}
+
+#[test]
+fn overlapping_watched_directories() {
+    let dir = TempDir::new().unwrap();
+    let root = dir.path();
+    repository(&root.join("a"));
+    repository(&root.join("deep/1/2/b"));
+
+    // Every watched directory is searched with its own settings. Repositories are found, if any
+    // of them reaches them, and are only reported once.
+    let mut outer = watched(&dir);
+    outer.max_depth = Some(2);
+    let inner = WatchedDirectory::new(root.join("deep"));
+    for watched in [[outer.clone(), inner.clone()], [inner, outer]] {
+        let (repositories, _) = discover(
+            &watched,
+            &Discovery::default(),
+            &Ignored::new([], []).unwrap(),
+            &DirectoryCache::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            relative(repositories.into_iter().map(|repo| repo.path), root),
+            vec![PathBuf::from("a"), PathBuf::from("deep/1/2/b")]
+        );
+    }
+}
+
+#[test]
+fn symlinked_watched_directory() {
+    let dir = TempDir::new().unwrap();
+    let real = dir.path().join("real");
+    let link = dir.path().join("link");
+    repository(&real.join("a"));
+    symlink(&real, &link).unwrap();
+
+    // Repositories keep the path of the watched directory, so hooks, settings and groups,
+    // which are configured for that path, still apply.
+    let mut config = GeilConfig::new();
+    config.watched = vec![WatchedDirectory::new(link.clone())];
+    let mut state = State::load(Some(dir.path().join("geil.state"))).unwrap();
+    state.scan(&config).unwrap();
+    let paths = |state: &State| -> Vec<PathBuf> {
+        state
+            .repositories
+            .iter()
+            .map(|repo| repo.path.clone())
+            .collect()
+    };
+    assert_eq!(paths(&state), vec![link.join("a")]);
+
+    // The repository isn't added again, if it's also reached via its real path.
+    config.watched.push(WatchedDirectory::new(real.clone()));
+    state.scan(&config).unwrap();
+    assert_eq!(paths(&state), vec![link.join("a")]);
+
+    // Repositories that are found multiple times in one run are only reported once.
+    let (repositories, _) = discover(
+        &config.watched,
+        &Discovery::default(),
+        &Ignored::new([], []).unwrap(),
+        &DirectoryCache::default(),
+    )
+    .unwrap();
+    let paths: Vec<PathBuf> = repositories.into_iter().map(|repo| repo.path).collect();
+    assert_eq!(paths, vec![real.join("a")]);
+}
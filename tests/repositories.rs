@@ -0,0 +1,1484 @@
+//! Check and update real repositories, which are cloned from local bare repositories.
+//!
+//! Every scenario is run against all git backends that have been compiled in.
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    io::{Read, Write},
+    net::TcpListener,
+    os::unix::fs::{PermissionsExt, symlink},
+    path::{Path, PathBuf},
+    process::{Command, Output},
+    sync::LazyLock,
+    thread,
+    time::{Duration, Instant},
+};
+
+use geil::{
+    commands::{add_key, check_repo_inner, exec_command, update_repo_inner},
+    config::{GeilConfig, Hook, SshKey, Strategy, Timeouts},
+    git::{CliBackend, GitBackend, PruneReason, environment, fetch, prunable_branches},
+    manifest::{Manifest, ManifestEntry},
+    process::{Cmd, set_interactive},
+    repository_info::{BranchOutcome, Finding, RepositoryInfo, UpdateOutcome},
+    ssh_key::{fingerprint, is_loaded, public_key_path},
+    state::State,
+};
+use indicatif::ProgressBar;
+use tempfile::TempDir;
+
+/// A temporary home for git's global config, so the tests never read the user's one.
+static GIT_HOME: LazyLock<TempDir> = LazyLock::new(|| {
+    let home = TempDir::new().expect("Failed to create git home");
+    fs::write(home.path().join("gitconfig"), "").expect("Failed to create git config");
+
+    home
+});
+
+/// The environment of every git process that's started by the tests.
+///
+/// It's passed to each process explicitly, as changing the environment of the test process
+/// itself isn't safe while other tests are running.
+fn git_env() -> HashMap<String, String> {
+    let git_config = GIT_HOME.path().join("gitconfig");
+    [
+        ("GIT_CONFIG_GLOBAL", git_config.to_string_lossy().as_ref()),
+        ("GIT_CONFIG_NOSYSTEM", "1"),
+        ("GIT_AUTHOR_NAME", "geil"),
+        ("GIT_AUTHOR_EMAIL", "geil@example.com"),
+        ("GIT_COMMITTER_NAME", "geil"),
+        ("GIT_COMMITTER_EMAIL", "geil@example.com"),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_string(), value.to_string()))
+    .collect()
+}
+
+/// Runs the geil binary with its own config and state in a temporary directory.
+///
+/// The paths are passed to every invocation, so the user's config and state are never touched.
+struct Geil {
+    dir: TempDir,
+}
+
+impl Geil {
+    /// Create a new sandbox without any config, so geil creates the default one.
+    fn new() -> Geil {
+        Geil {
+            dir: TempDir::new().expect("Failed to create temporary directory"),
+        }
+    }
+
+    /// Create a new sandbox with the given config.
+    fn with_config(config: &str) -> Geil {
+        let geil = Geil::new();
+        fs::write(geil.config_path(), config).expect("Failed to write config");
+
+        geil
+    }
+
+    fn config_path(&self) -> PathBuf {
+        self.dir.path().join("geil.yml")
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.dir.path().join("geil.state")
+    }
+
+    /// Run geil with the given arguments.
+    fn command(&self, args: &[&str]) -> Output {
+        Command::new(env!("CARGO_BIN_EXE_geil"))
+            .arg("--config")
+            .arg(self.config_path())
+            .arg("--state")
+            .arg(self.state_path())
+            .args(args)
+            .envs(git_env())
+            .output()
+            .expect("Failed to run geil")
+    }
+
+    /// Run geil with the given arguments, expect it to succeed and return its output.
+    fn run(&self, args: &[&str]) -> String {
+        let output = self.command(args);
+        assert!(
+            output.status.success(),
+            "geil {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr)
+        );
+
+        String::from_utf8_lossy(&output.stdout).to_string()
+    }
+
+    /// Load the state that has been written by geil.
+    fn state(&self) -> State {
+        State::load(Some(self.state_path())).expect("Failed to load state")
+    }
+}
+
+/// A bare repository, which acts as the remote for all clones of a test.
+struct Remote {
+    dir: TempDir,
+    /// A clone, which is used to push new commits to the remote.
+    upstream: PathBuf,
+}
+
+impl Remote {
+    /// Create a remote with a single commit on `main`.
+    fn new() -> Remote {
+        let dir = TempDir::new().expect("Failed to create temporary directory");
+        git(
+            dir.path(),
+            &["init", "--quiet", "--bare", "-b", "main", "remote.git"],
+        );
+
+        let remote = Remote {
+            upstream: dir.path().join("upstream"),
+            dir,
+        };
+        git(
+            remote.dir.path(),
+            &["clone", "--quiet", "remote.git", "upstream"],
+        );
+        commit(&remote.upstream, "initial");
+        git(&remote.upstream, &["push", "--quiet", "origin", "main"]);
+
+        remote
+    }
+
+    /// Clone the remote into a new repository with the given name.
+    fn clone(&self, name: &str) -> PathBuf {
+        self.clone_into(self.dir.path(), name)
+    }
+
+    /// Clone the remote into a new repository at the given path inside of `dir`.
+    fn clone_into(&self, dir: &Path, path: &str) -> PathBuf {
+        let target = dir.join(path);
+        git(
+            self.dir.path(),
+            &["clone", "--quiet", "remote.git", &target.to_string_lossy()],
+        );
+
+        target
+    }
+
+    /// The url of the remote, which can be used by clones.
+    fn url(&self) -> String {
+        self.dir
+            .path()
+            .join("remote.git")
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// Push a new commit to the remote, so existing clones fall behind.
+    fn push_commit(&self, message: &str) {
+        git(&self.upstream, &["pull", "--quiet"]);
+        commit(&self.upstream, message);
+        git(&self.upstream, &["push", "--quiet", "origin", "main"]);
+    }
+
+    /// Push a new commit to another existing branch of the remote.
+    fn push_branch_commit(&self, branch: &str, message: &str) {
+        let upstream_branch = format!("origin/{branch}");
+        git(&self.upstream, &["fetch", "--quiet"]);
+        git(
+            &self.upstream,
+            &["checkout", "--quiet", "-B", branch, &upstream_branch],
+        );
+        commit(&self.upstream, message);
+        git(&self.upstream, &["push", "--quiet", "origin", branch]);
+        git(&self.upstream, &["checkout", "--quiet", "main"]);
+    }
+}
+
+/// Run git inside the given directory and return its output.
+fn git(dir: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .envs(git_env())
+        .output()
+        .expect("Failed to run git");
+    assert!(
+        output.status.success(),
+        "git {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+/// Get the name of the checked out branch.
+fn current_branch(repo: &Path) -> String {
+    git(repo, &["symbolic-ref", "--short", "HEAD"])
+}
+
+/// Create a commit that changes the tracked `file`.
+fn commit(repo: &Path, message: &str) {
+    let file = repo.join("file");
+    let content = fs::read_to_string(&file).unwrap_or_default();
+    fs::write(&file, format!("{content}{message}\n")).unwrap();
+    git(repo, &["add", "file"]);
+    git(repo, &["commit", "--quiet", "-m", message]);
+}
+
+/// All backends the tests should run against.
+fn backends() -> Vec<Box<dyn GitBackend>> {
+    let mut envs = environment(false);
+    envs.extend(git_env());
+
+    #[allow(unused_mut)]
+    let mut backends: Vec<Box<dyn GitBackend>> = vec![Box::new(CliBackend::new(envs.clone()))];
+    #[cfg(feature = "native-git")]
+    backends.push(Box::new(geil::git::NativeBackend::new(envs)));
+
+    backends
+}
+
+fn check(repo: &Path, backend: &dyn GitBackend, ignore_untracked: bool) -> RepositoryInfo {
+    let mut repo_info = RepositoryInfo::new(repo.to_path_buf(), None, None, Vec::new());
+    check_repo_inner(
+        &ProgressBar::hidden(),
+        &mut repo_info,
+        backend,
+        ignore_untracked,
+    )
+    .expect("Check failed");
+
+    repo_info
+}
+
+fn update(repo: &Path, backend: &dyn GitBackend) -> RepositoryInfo {
+    let repo_info = RepositoryInfo::new(repo.to_path_buf(), None, None, Vec::new());
+    update_info(repo_info, backend)
+}
+
+fn update_info(mut repo_info: RepositoryInfo, backend: &dyn GitBackend) -> RepositoryInfo {
+    update_repo_inner(&ProgressBar::hidden(), &mut repo_info, backend, false)
+        .expect("Update failed");
+
+    repo_info
+}
+
+fn assert_findings(repo_info: &RepositoryInfo, expected: &[Finding]) {
+    let expected: BTreeSet<Finding> = expected.iter().copied().collect();
+    assert_eq!(repo_info.findings, expected, "error: {:?}", repo_info.error);
+}
+
+#[test]
+fn clean() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("clean");
+
+        let repo_info = check(&repo, backend.as_ref(), false);
+        assert_findings(&repo_info, &[]);
+        assert!(matches!(repo_info.outcome, UpdateOutcome::Checked));
+        assert_eq!(repo_info.ahead, Some(0));
+        assert_eq!(repo_info.behind, Some(0));
+
+        let repo_info = update(&repo, backend.as_ref());
+        assert_findings(&repo_info, &[]);
+        assert!(matches!(repo_info.outcome, UpdateOutcome::UpToDate));
+    }
+}
+
+#[test]
+fn dirty() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("dirty");
+        fs::write(repo.join("file"), "changed\n").unwrap();
+        fs::write(repo.join("staged"), "new\n").unwrap();
+        git(&repo, &["add", "staged"]);
+
+        let repo_info = check(&repo, backend.as_ref(), false);
+        assert_findings(&repo_info, &[Finding::LocalChanges]);
+        assert_eq!(repo_info.changes.modified, 1);
+        assert_eq!(repo_info.changes.staged, 1);
+        assert_eq!(repo_info.changes.untracked, 0);
+    }
+}
+
+#[test]
+fn untracked() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("untracked");
+        fs::write(repo.join("untracked"), "new\n").unwrap();
+
+        let repo_info = check(&repo, backend.as_ref(), false);
+        assert_findings(&repo_info, &[Finding::LocalChanges]);
+        assert_eq!(repo_info.changes.untracked, 1);
+
+        // Only untracked files are considered clean, if they're ignored.
+        let repo_info = check(&repo, backend.as_ref(), true);
+        assert_findings(&repo_info, &[]);
+    }
+}
+
+#[test]
+fn stashed() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("stashed");
+        for content in ["first\n", "second\n"] {
+            fs::write(repo.join("file"), content).unwrap();
+            git(&repo, &["stash", "--quiet"]);
+        }
+
+        let repo_info = check(&repo, backend.as_ref(), false);
+        assert_findings(&repo_info, &[Finding::Stashed]);
+        assert_eq!(repo_info.stashed, 2);
+    }
+}
+
+#[test]
+fn detached() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("detached");
+        git(&repo, &["checkout", "--quiet", "--detach"]);
+
+        let repo_info = check(&repo, backend.as_ref(), false);
+        assert_findings(&repo_info, &[Finding::Detached]);
+
+        // Detached repositories are still fetched, but never moved.
+        remote.push_commit("remote");
+        let head = git(&repo, &["rev-parse", "HEAD"]);
+        let repo_info = update(&repo, backend.as_ref());
+        assert_findings(&repo_info, &[Finding::Detached]);
+        assert!(matches!(repo_info.outcome, UpdateOutcome::Fetched));
+        assert_eq!(git(&repo, &["rev-parse", "HEAD"]), head);
+    }
+}
+
+#[test]
+fn no_upstream() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("no_upstream");
+        git(&repo, &["checkout", "--quiet", "-b", "feature"]);
+        commit(&repo, "feature");
+
+        let repo_info = check(&repo, backend.as_ref(), false);
+        assert_findings(
+            &repo_info,
+            &[Finding::NoUpstream, Finding::NotOnDefaultBranch],
+        );
+        assert_eq!(repo_info.ahead, None);
+        assert_eq!(repo_info.behind, None);
+    }
+}
+
+#[test]
+fn unpushed() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("unpushed");
+        commit(&repo, "local");
+
+        let repo_info = check(&repo, backend.as_ref(), false);
+        assert_findings(&repo_info, &[Finding::NotPushed]);
+        assert_eq!(repo_info.ahead, Some(1));
+        assert_eq!(repo_info.behind, Some(0));
+
+        let repo_info = update(&repo, backend.as_ref());
+        assert_findings(&repo_info, &[Finding::NotPushed]);
+        assert!(matches!(repo_info.outcome, UpdateOutcome::UpToDate));
+    }
+}
+
+#[test]
+fn fast_forwardable() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("fast_forwardable");
+        remote.push_commit("remote");
+
+        // Checks don't fetch, so the new commit is only known after fetching.
+        let repo_info = check(&repo, backend.as_ref(), false);
+        assert_findings(&repo_info, &[]);
+        git(&repo, &["fetch", "--quiet"]);
+        let repo_info = check(&repo, backend.as_ref(), false);
+        assert_findings(&repo_info, &[Finding::Behind]);
+        assert_eq!(repo_info.behind, Some(1));
+
+        let repo_info = update(&repo, backend.as_ref());
+        assert_findings(&repo_info, &[]);
+        assert!(matches!(repo_info.outcome, UpdateOutcome::Updated));
+        assert_eq!(repo_info.behind, Some(0));
+        assert_eq!(
+            git(&repo, &["rev-parse", "HEAD"]),
+            git(&repo, &["rev-parse", "origin/main"])
+        );
+        // The working tree has been updated as well.
+        assert_eq!(
+            fs::read_to_string(repo.join("file")).unwrap(),
+            "initial\nremote\n"
+        );
+        assert_eq!(git(&repo, &["status", "--porcelain"]), "");
+    }
+}
+
+#[test]
+fn diverged() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("diverged");
+        commit(&repo, "local");
+        remote.push_commit("remote");
+        let head = git(&repo, &["rev-parse", "HEAD"]);
+
+        let repo_info = update(&repo, backend.as_ref());
+        assert_findings(
+            &repo_info,
+            &[Finding::NoFastForward, Finding::NotPushed, Finding::Behind],
+        );
+        assert!(matches!(repo_info.outcome, UpdateOutcome::Fetched));
+        assert_eq!(repo_info.ahead, Some(1));
+        assert_eq!(repo_info.behind, Some(1));
+        assert_eq!(git(&repo, &["rev-parse", "HEAD"]), head);
+    }
+}
+
+#[test]
+fn dirty_and_behind() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("dirty_and_behind");
+        fs::write(repo.join("other"), "new\n").unwrap();
+        git(&repo, &["add", "other"]);
+        remote.push_commit("remote");
+        let head = git(&repo, &["rev-parse", "HEAD"]);
+
+        // Repositories with local changes are never touched.
+        let repo_info = update(&repo, backend.as_ref());
+        assert_findings(&repo_info, &[Finding::LocalChanges, Finding::Behind]);
+        assert!(matches!(repo_info.outcome, UpdateOutcome::Fetched));
+        assert_eq!(git(&repo, &["rev-parse", "HEAD"]), head);
+    }
+}
+
+#[test]
+fn all_branches() {
+    for backend in backends() {
+        let remote = Remote::new();
+        for branch in ["develop", "release", "other"] {
+            git(
+                &remote.upstream,
+                &["push", "--quiet", "origin", &format!("main:{branch}")],
+            );
+        }
+        let repo = remote.clone("all_branches");
+        for branch in ["develop", "release", "other"] {
+            let upstream = format!("origin/{branch}");
+            git(&repo, &["branch", "--quiet", "--track", branch, &upstream]);
+        }
+        // A branch without upstream, which is never touched.
+        git(&repo, &["branch", "--quiet", "feature"]);
+        // A branch with local commits, which cannot be fast-forwarded.
+        git(&repo, &["checkout", "--quiet", "release"]);
+        commit(&repo, "local");
+        git(&repo, &["checkout", "--quiet", "main"]);
+        let release = git(&repo, &["rev-parse", "release"]);
+
+        remote.push_branch_commit("develop", "develop");
+        remote.push_branch_commit("release", "release");
+
+        // Only the checked out branch is updated by default.
+        let repo_info = update(&repo, backend.as_ref());
+        assert!(repo_info.branches.is_empty());
+
+        let mut repo_info = RepositoryInfo::new(repo.clone(), None, None, Vec::new());
+        repo_info.all_branches = true;
+        let repo_info = update_info(repo_info, backend.as_ref());
+        assert_findings(&repo_info, &[]);
+
+        let outcomes: Vec<(&str, BranchOutcome)> = repo_info
+            .branches
+            .iter()
+            .map(|branch| (branch.name.as_str(), branch.outcome))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                ("develop", BranchOutcome::Updated),
+                ("other", BranchOutcome::UpToDate),
+                ("release", BranchOutcome::NoFastForward),
+            ]
+        );
+        assert_eq!(
+            git(&repo, &["rev-parse", "develop"]),
+            git(&repo, &["rev-parse", "origin/develop"])
+        );
+        assert_eq!(git(&repo, &["rev-parse", "release"]), release);
+        // The working tree of the checked out branch isn't touched.
+        assert_eq!(git(&repo, &["status", "--porcelain"]), "");
+    }
+}
+
+#[test]
+fn merged_branch() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("merged_branch");
+        git(&repo, &["checkout", "--quiet", "-b", "feature"]);
+        commit(&repo, "feature");
+        git(
+            &repo,
+            &["push", "--quiet", "--set-upstream", "origin", "feature"],
+        );
+
+        // The feature is merged, so the default branch points to the same commit.
+        git(&repo, &["push", "--quiet", "origin", "feature:main"]);
+        git(&repo, &["fetch", "--quiet"]);
+        let repo_info = check(&repo, backend.as_ref(), false);
+        assert_findings(&repo_info, &[Finding::NotOnDefaultBranch, Finding::Merged]);
+
+        // Development on the default branch continues.
+        remote.push_commit("remote");
+        git(&repo, &["fetch", "--quiet"]);
+
+        let repo_info = check(&repo, backend.as_ref(), false);
+        assert_findings(&repo_info, &[Finding::NotOnDefaultBranch, Finding::Merged]);
+        assert_eq!(repo_info.default_branch.as_deref(), Some("main"));
+        assert_eq!(repo_info.behind_default, Some(1));
+
+        // Merged branches are left alone by default.
+        let repo_info = update(&repo, backend.as_ref());
+        assert_findings(&repo_info, &[Finding::NotOnDefaultBranch, Finding::Merged]);
+        assert_eq!(current_branch(&repo), "feature");
+
+        let mut repo_info = RepositoryInfo::new(repo.clone(), None, None, Vec::new());
+        repo_info.switch_merged = true;
+        let repo_info = update_info(repo_info, backend.as_ref());
+        assert_findings(&repo_info, &[Finding::Switched]);
+        assert_eq!(repo_info.switched_from.as_deref(), Some("feature"));
+        // The default branch is updated right after switching to it.
+        assert!(matches!(repo_info.outcome, UpdateOutcome::Updated));
+        assert_eq!(current_branch(&repo), "main");
+        assert_eq!(
+            git(&repo, &["rev-parse", "HEAD"]),
+            git(&repo, &["rev-parse", "origin/main"])
+        );
+    }
+}
+
+#[test]
+fn unmerged_branch() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("unmerged_branch");
+        git(&repo, &["checkout", "--quiet", "-b", "feature"]);
+        commit(&repo, "feature");
+        git(
+            &repo,
+            &["push", "--quiet", "--set-upstream", "origin", "feature"],
+        );
+        remote.push_commit("remote");
+
+        let mut repo_info = RepositoryInfo::new(repo.clone(), None, None, Vec::new());
+        repo_info.switch_merged = true;
+        let repo_info = update_info(repo_info, backend.as_ref());
+        assert_findings(&repo_info, &[Finding::NotOnDefaultBranch]);
+        assert_eq!(repo_info.behind_default, Some(1));
+        assert_eq!(current_branch(&repo), "feature");
+    }
+}
+
+#[test]
+fn new_branch() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("new_branch");
+
+        // Branches without commits of their own haven't been merged, no matter whether they
+        // track the default branch or not.
+        for (branch, start) in [("untracked", "main"), ("tracking", "origin/main")] {
+            git(&repo, &["checkout", "--quiet", "-b", branch, start]);
+
+            let mut repo_info = RepositoryInfo::new(repo.clone(), None, None, Vec::new());
+            repo_info.switch_merged = true;
+            let repo_info = update_info(repo_info, backend.as_ref());
+            assert!(!repo_info.has(Finding::Merged), "{branch}");
+            assert!(!repo_info.has(Finding::Switched), "{branch}");
+            assert_eq!(current_branch(&repo), branch);
+        }
+    }
+}
+
+/// List the names of all local branches.
+fn local_branches(repo: &Path) -> Vec<String> {
+    git(
+        repo,
+        &["for-each-ref", "--format=%(refname:short)", "refs/heads"],
+    )
+    .lines()
+    .map(String::from)
+    .collect()
+}
+
+/// Create a branch with a commit, push it and delete it on the remote again.
+/// The branch is based on `start` and stays checked out.
+fn gone_branch(remote: &Remote, repo: &Path, branch: &str, start: &str) {
+    git(repo, &["checkout", "--quiet", "-b", branch, start]);
+    commit(repo, branch);
+    git(
+        repo,
+        &["push", "--quiet", "--set-upstream", "origin", branch],
+    );
+    git(
+        &remote.upstream,
+        &["push", "--quiet", "origin", "--delete", branch],
+    );
+}
+
+#[test]
+fn prune() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("prune");
+
+        // A branch that has been merged and deleted on the remote.
+        gone_branch(&remote, &repo, "done", "main");
+        git(&repo, &["push", "--quiet", "origin", "done:main"]);
+
+        // A branch that has been deleted on the remote without being merged, e.g. because
+        // it has been squash merged.
+        gone_branch(&remote, &repo, "gone", "main");
+
+        // A branch that has been merged into the default branch.
+        git(&repo, &["checkout", "--quiet", "-b", "merged", "done"]);
+        commit(&repo, "merged");
+        git(&repo, &["push", "--quiet", "origin", "merged:main"]);
+        remote.push_commit("later");
+
+        // A branch with commits that only exist locally.
+        git(&repo, &["checkout", "--quiet", "-b", "unmerged", "main"]);
+        commit(&repo, "unmerged");
+
+        // The checked out branch is never pruned, even though it's merged.
+        git(
+            &repo,
+            &["checkout", "--quiet", "-b", "current", "origin/main"],
+        );
+
+        let mut repo_info = RepositoryInfo::new(repo.clone(), None, None, Vec::new());
+        repo_info.prune = true;
+        fetch(&mut repo_info, backend.as_ref()).unwrap();
+        assert!(
+            git(&repo, &["branch", "--remotes"])
+                .lines()
+                .all(|line| !line.contains("gone") && !line.contains("done"))
+        );
+
+        // New branches without any commits of their own haven't been merged yet, no matter
+        // whether they track the default branch or not.
+        git(&repo, &["branch", "--quiet", "fresh", "origin/main"]);
+        git(
+            &repo,
+            &["branch", "--quiet", "--no-track", "new", "origin/main"],
+        );
+
+        let branches = prunable_branches(&repo_info, backend.as_ref()).unwrap();
+        let branches: Vec<(&str, PruneReason)> = branches
+            .iter()
+            .map(|branch| (branch.name.as_str(), branch.reason))
+            .collect();
+        assert_eq!(
+            branches,
+            vec![
+                ("done", PruneReason::UpstreamGone),
+                ("gone", PruneReason::Unmerged),
+                ("merged", PruneReason::Merged),
+            ]
+        );
+
+        backend.delete_branch(&repo_info, "done").unwrap();
+        backend.delete_branch(&repo_info, "merged").unwrap();
+        assert_eq!(
+            local_branches(&repo),
+            vec!["current", "fresh", "gone", "main", "new", "unmerged"]
+        );
+    }
+}
+
+#[test]
+fn prune_unmerged_branches_only_with_force() {
+    let remote = Remote::new();
+    let repo = remote.clone("prune_unmerged");
+    gone_branch(&remote, &repo, "gone", "main");
+    git(&repo, &["checkout", "--quiet", "main"]);
+    let geil = Geil::with_config(&format!(
+        "watched: []\nrepositories:\n- {}\n",
+        repo.to_string_lossy()
+    ));
+
+    // The branch is listed, but its commit would be lost, so it's kept.
+    let output = geil.run(&["prune", "--yes"]);
+    assert!(output.contains("--force"), "{output}");
+    assert_eq!(local_branches(&repo), vec!["gone", "main"]);
+
+    geil.run(&["prune", "--yes", "--force"]);
+    assert_eq!(local_branches(&repo), vec!["main"]);
+}
+
+/// Update the repository with the given strategy.
+fn update_with(repo: &Path, backend: &dyn GitBackend, strategy: Strategy) -> RepositoryInfo {
+    let mut repo_info = RepositoryInfo::new(repo.to_path_buf(), None, None, Vec::new());
+    repo_info.strategy = strategy;
+    update_info(repo_info, backend)
+}
+
+/// Clone a repository with a second tracked file `notes`, which isn't touched by the remote.
+fn clone_with_notes(remote: &Remote, name: &str) -> PathBuf {
+    git(&remote.upstream, &["pull", "--quiet"]);
+    fs::write(remote.upstream.join("notes"), "notes\n").unwrap();
+    git(&remote.upstream, &["add", "notes"]);
+    git(&remote.upstream, &["commit", "--quiet", "-m", "notes"]);
+    git(&remote.upstream, &["push", "--quiet", "origin", "main"]);
+
+    remote.clone(name)
+}
+
+#[test]
+fn rebase() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = clone_with_notes(&remote, "rebase");
+        fs::write(repo.join("notes"), "local\n").unwrap();
+        git(&repo, &["commit", "--quiet", "-am", "local"]);
+        remote.push_commit("remote");
+
+        let repo_info = update_with(&repo, backend.as_ref(), Strategy::Rebase);
+        assert_findings(&repo_info, &[Finding::NotPushed]);
+        assert!(matches!(repo_info.outcome, UpdateOutcome::Updated));
+        assert_eq!(repo_info.ahead, Some(1));
+        assert_eq!(repo_info.behind, Some(0));
+        assert_eq!(git(&repo, &["log", "-1", "--format=%s"]), "local");
+    }
+}
+
+#[test]
+fn rebase_conflict() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("rebase_conflict");
+        commit(&repo, "local");
+        remote.push_commit("remote");
+        let head = git(&repo, &["rev-parse", "HEAD"]);
+
+        let repo_info = update_with(&repo, backend.as_ref(), Strategy::Rebase);
+        assert!(matches!(repo_info.outcome, UpdateOutcome::RolledBack));
+        assert!(repo_info.has(Finding::UpdateConflict));
+        assert_eq!(git(&repo, &["rev-parse", "HEAD"]), head);
+        assert_eq!(git(&repo, &["status", "--porcelain"]), "");
+        assert_eq!(current_branch(&repo), "main");
+    }
+}
+
+#[test]
+fn stashing_strategies() {
+    for strategy in [Strategy::RebaseAutostash, Strategy::StashPullPop] {
+        for backend in backends() {
+            let remote = Remote::new();
+            let repo = clone_with_notes(&remote, "stashing");
+            fs::write(repo.join("notes"), "changed\n").unwrap();
+            remote.push_commit("remote");
+
+            let repo_info = update_with(&repo, backend.as_ref(), strategy);
+            assert_findings(&repo_info, &[Finding::LocalChanges]);
+            assert!(matches!(repo_info.outcome, UpdateOutcome::UpdatedWithStash));
+            assert_eq!(
+                git(&repo, &["rev-parse", "HEAD"]),
+                git(&repo, &["rev-parse", "origin/main"])
+            );
+            // The local changes are back and the stash is empty again.
+            assert_eq!(fs::read_to_string(repo.join("notes")).unwrap(), "changed\n");
+            assert_eq!(git(&repo, &["stash", "list"]), "");
+        }
+    }
+}
+
+#[test]
+fn stashing_strategies_untracked() {
+    for strategy in [Strategy::RebaseAutostash, Strategy::StashPullPop] {
+        for backend in backends() {
+            let remote = Remote::new();
+            let repo = remote.clone("stashing_untracked");
+            fs::write(repo.join("untracked"), "new\n").unwrap();
+            remote.push_commit("remote");
+
+            // Untracked files aren't stashed, so there's nothing to restore.
+            let repo_info = update_with(&repo, backend.as_ref(), strategy);
+            assert_findings(&repo_info, &[Finding::LocalChanges]);
+            assert!(
+                matches!(repo_info.outcome, UpdateOutcome::Updated),
+                "{strategy:?}: {}",
+                repo_info.outcome
+            );
+            assert_eq!(
+                git(&repo, &["rev-parse", "HEAD"]),
+                git(&repo, &["rev-parse", "origin/main"])
+            );
+            assert_eq!(fs::read_to_string(repo.join("untracked")).unwrap(), "new\n");
+            assert_eq!(git(&repo, &["stash", "list"]), "");
+        }
+    }
+}
+
+#[test]
+fn stashing_strategies_conflict() {
+    for strategy in [Strategy::RebaseAutostash, Strategy::StashPullPop] {
+        for backend in backends() {
+            let remote = Remote::new();
+            let repo = remote.clone("stashing_conflict");
+            fs::write(repo.join("file"), "changed\n").unwrap();
+            remote.push_commit("remote");
+            let head = git(&repo, &["rev-parse", "HEAD"]);
+
+            let repo_info = update_with(&repo, backend.as_ref(), strategy);
+            assert!(
+                matches!(repo_info.outcome, UpdateOutcome::RolledBack),
+                "{strategy:?}: {:?}",
+                repo_info.error
+            );
+            assert!(repo_info.has(Finding::UpdateConflict));
+            // Everything is like before the update.
+            assert_eq!(git(&repo, &["rev-parse", "HEAD"]), head);
+            assert_eq!(fs::read_to_string(repo.join("file")).unwrap(), "changed\n");
+            assert_eq!(git(&repo, &["status", "--porcelain"]), "M file");
+            assert_eq!(git(&repo, &["stash", "list"]), "");
+        }
+    }
+}
+
+#[test]
+fn exec_keeps_quoting() {
+    let remote = Remote::new();
+    let repo = remote.clone("exec");
+
+    // Every argument is passed to the program as it is, no matter how many there are.
+    let args = ["printf", "%s|", "a b", "c;d"].map(String::from);
+    let capture = exec_command(&args, false).cwd(repo.clone()).run().unwrap();
+    assert!(capture.success());
+    assert_eq!(String::from_utf8_lossy(&capture.stdout), "a b|c;d|");
+
+    let args = ["printf a && printf b".to_string()];
+    let capture = exec_command(&args, false).cwd(repo.clone()).run();
+    assert!(capture.is_err_and(|err| format!("{err:#}").contains("printf a && printf b")));
+
+    // With `--shell`, the arguments are a command line for the shell.
+    let capture = exec_command(&args, true).cwd(repo.clone()).run().unwrap();
+    assert_eq!(String::from_utf8_lossy(&capture.stdout), "ab");
+    let args = ["printf", "a", "&&", "printf", "b"].map(String::from);
+    let capture = exec_command(&args, true).cwd(repo).run().unwrap();
+    assert_eq!(String::from_utf8_lossy(&capture.stdout), "ab");
+}
+
+#[test]
+fn binary_uses_given_config_and_state() {
+    let geil = Geil::new();
+    geil.run(&["scan"]);
+
+    assert!(geil.config_path().exists());
+    assert!(geil.state_path().exists());
+}
+
+#[test]
+fn custom_config_and_state_paths() {
+    let dir = TempDir::new().expect("Failed to create temporary directory");
+    let config_path = dir.path().join("work.yml");
+    let state_path = dir.path().join("work.state");
+
+    GeilConfig::load(Some(config_path.clone())).expect("Failed to create config");
+    assert!(config_path.exists());
+
+    let mut state = State::load(Some(state_path.clone())).expect("Failed to load state");
+    state.save().expect("Failed to save state");
+    assert!(state_path.exists());
+
+    // The config is read from the given file.
+    fs::write(
+        &config_path,
+        "# Comment\nwatched: []\nrepositories: []\nignored:\n- \"**/node_modules\"\n",
+    )
+    .unwrap();
+    let config = GeilConfig::load(Some(config_path.clone())).expect("Failed to load config");
+    assert_eq!(config.ignored, vec![PathBuf::from("**/node_modules")]);
+
+    // Changes of commands end up in the state, the user's config is left alone.
+    let mut state = State::load(Some(state_path)).expect("Failed to load state");
+    if let Some(key) = ssh_key(dir.path()) {
+        add_key(&mut state, &config, key, None, false).expect("Failed to add key");
+        assert_eq!(state.keys(&config).len(), 1);
+        assert!(
+            fs::read_to_string(&config_path)
+                .unwrap()
+                .starts_with("# Comment")
+        );
+    }
+}
+
+/// Generate a new ssh key pair in the directory.
+/// Returns `None`, if `ssh-keygen` isn't available.
+fn ssh_key(dir: &Path) -> Option<PathBuf> {
+    let key = dir.join("id_ed25519");
+    let keygen = Command::new("ssh-keygen")
+        .args(["-q", "-t", "ed25519", "-C", "original", "-N", "", "-f"])
+        .arg(&key)
+        .status();
+
+    keygen.is_ok_and(|status| status.success()).then_some(key)
+}
+
+#[test]
+fn loaded_keys_ignore_comments() {
+    let dir = TempDir::new().expect("Failed to create temporary directory");
+    let Some(path) = ssh_key(dir.path()) else {
+        return;
+    };
+    let key = SshKey::new("key".into(), path.clone(), true);
+    let loaded = vec![fingerprint(&path).unwrap()];
+
+    // Agents often know keys with another comment than the one in the public key file.
+    let public_key = public_key_path(&path);
+    let content = fs::read_to_string(&public_key).unwrap();
+    fs::write(&public_key, content.replace("original", "changed")).unwrap();
+
+    assert!(is_loaded(&key, &loaded).unwrap());
+    assert!(!is_loaded(&key, &["SHA256:other".to_string()]).unwrap());
+}
+
+#[test]
+fn manifest_paths_stay_inside_watched_directories() {
+    let entry = |watched: Option<&str>, path: &str| ManifestEntry {
+        path: path.into(),
+        watched: watched.map(PathBuf::from),
+        remotes: BTreeMap::new(),
+        default_branch: None,
+        hook: None,
+    };
+
+    let watched = [
+        PathBuf::from("/home/me/projects"),
+        PathBuf::from("/srv/work"),
+    ];
+    let target = |entry: ManifestEntry| entry.target_path(&watched);
+
+    assert_eq!(
+        target(entry(Some("/home/me/projects"), "geil")).unwrap(),
+        PathBuf::from("/home/me/projects/geil")
+    );
+    // Watched directories with another path are found by their name.
+    assert_eq!(
+        target(entry(Some("/home/other/work"), "a/geil")).unwrap(),
+        PathBuf::from("/srv/work/a/geil")
+    );
+    assert_eq!(
+        target(entry(None, "/srv/work/geil")).unwrap(),
+        PathBuf::from("/srv/work/geil")
+    );
+
+    // The manifest's watched directory has to be watched on this machine.
+    assert!(target(entry(Some("/home/me/other"), "geil")).is_err());
+    assert!(target(entry(None, "/opt/geil")).is_err());
+    assert!(target(entry(None, "/srv/work/../../etc/geil")).is_err());
+    assert!(target(entry(None, "geil")).is_err());
+
+    assert!(target(entry(Some("/srv/work"), "../geil")).is_err());
+    assert!(target(entry(Some("/srv/work"), "a/../../geil")).is_err());
+    assert!(target(entry(Some("/srv/work"), "/etc/geil")).is_err());
+    assert!(target(entry(Some("/srv/work"), "")).is_err());
+}
+
+/// Start a server, which accepts connections but never answers.
+/// Returns a `git://` url pointing to it.
+fn hanging_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind server");
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let mut connections = Vec::new();
+        for stream in listener.incoming() {
+            connections.push(stream);
+        }
+    });
+
+    format!("git://{address}/remote.git")
+}
+
+#[test]
+fn hanging_fetch_times_out() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("hanging");
+        git(&repo, &["remote", "set-url", "origin", &hanging_server()]);
+
+        let mut repo_info = RepositoryInfo::new(repo.clone(), None, None, Vec::new());
+        repo_info.start_timeouts(&Timeouts {
+            command: Some(1),
+            repository: None,
+        });
+        let start = Instant::now();
+        if let Err(err) = update_repo_inner(
+            &ProgressBar::hidden(),
+            &mut repo_info,
+            backend.as_ref(),
+            false,
+        ) {
+            repo_info.set_error(err);
+        }
+
+        assert_findings(&repo_info, &[Finding::TimedOut]);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
+
+#[test]
+fn hanging_hook_times_out() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("hanging_hook");
+        remote.push_commit("new");
+
+        let hook = Hook::new(repo.clone(), "exec sleep 30".into());
+        let mut repo_info = RepositoryInfo::new(repo.clone(), Some(&hook), None, Vec::new());
+        repo_info.start_timeouts(&Timeouts {
+            command: Some(1),
+            repository: None,
+        });
+        let start = Instant::now();
+        if let Err(err) = update_repo_inner(
+            &ProgressBar::hidden(),
+            &mut repo_info,
+            backend.as_ref(),
+            false,
+        ) {
+            repo_info.set_error(err);
+        }
+
+        assert_findings(&repo_info, &[Finding::TimedOut]);
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+}
+
+/// Get the process group of a process.
+fn process_group(pid: &str) -> String {
+    let output = Command::new("ps")
+        .args(["-o", "pgid=", "-p", pid])
+        .output()
+        .expect("Failed to run ps");
+
+    String::from_utf8_lossy(&output.stdout).trim().to_string()
+}
+
+#[test]
+fn process_groups() {
+    let own_group = process_group(&std::process::id().to_string());
+    let child_group = || {
+        let capture = Cmd::shell("ps -o pgid= -p $$").run().unwrap();
+        String::from_utf8_lossy(&capture.stdout).trim().to_string()
+    };
+
+    // Interactive commands stay in the foreground group of the terminal, so they can still
+    // read from it.
+    assert_eq!(child_group(), own_group);
+
+    // Non-interactive commands get their own group, so they can be terminated with all of
+    // their children.
+    set_interactive(false);
+    let group = child_group();
+    set_interactive(true);
+    assert_ne!(group, own_group);
+}
+
+/// Start a server, which answers every request with `401 Unauthorized`.
+/// Returns an `http://` url pointing to it.
+fn unauthorized_server() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind server");
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for mut stream in listener.incoming().flatten() {
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request);
+            let _ = stream.write_all(
+                b"HTTP/1.1 401 Unauthorized\r\n\
+                WWW-Authenticate: Basic realm=\"geil\"\r\n\
+                Content-Length: 0\r\n\
+                Connection: close\r\n\r\n",
+            );
+        }
+    });
+
+    format!("http://{address}/remote.git")
+}
+
+#[test]
+fn authentication_required() {
+    for backend in backends() {
+        let remote = Remote::new();
+        let repo = remote.clone("authentication");
+        git(
+            &repo,
+            &["remote", "set-url", "origin", &unauthorized_server()],
+        );
+
+        // There's nobody to ask for credentials, so the fetch fails right away.
+        let mut repo_info = RepositoryInfo::new(repo.clone(), None, None, Vec::new());
+        if let Err(err) = update_repo_inner(
+            &ProgressBar::hidden(),
+            &mut repo_info,
+            backend.as_ref(),
+            false,
+        ) {
+            repo_info.set_error(err);
+        }
+
+        assert_findings(&repo_info, &[Finding::AuthenticationRequired]);
+    }
+}
+
+#[test]
+fn custom_ssh_command() {
+    let remote = Remote::new();
+    let repo = remote.clone("ssh_command");
+    git(
+        &repo,
+        &[
+            "remote",
+            "set-url",
+            "origin",
+            "git@example.invalid:remote.git",
+        ],
+    );
+
+    // A wrapper, which records how it has been called instead of connecting anywhere.
+    let log = repo.join("ssh.log");
+    let ssh = repo.join("ssh-wrapper");
+    fs::write(
+        &ssh,
+        format!("#!/bin/sh\necho \"$@\" >> {}\nexit 1\n", log.display()),
+    )
+    .unwrap();
+    fs::set_permissions(&ssh, fs::Permissions::from_mode(0o755)).unwrap();
+    git(
+        &repo,
+        &["config", "core.sshCommand", &ssh.to_string_lossy()],
+    );
+
+    // The configured ssh command is used as it is, instead of being replaced by plain ssh.
+    let mut envs = environment(false);
+    envs.extend(git_env());
+    let backend = CliBackend::new(envs);
+    let repo_info = RepositoryInfo::new(repo.clone(), None, None, Vec::new());
+    assert!(backend.fetch(&repo_info).is_err());
+
+    let calls = fs::read_to_string(&log).expect("The ssh command hasn't been used");
+    assert!(calls.contains("example.invalid"), "{calls}");
+    assert!(!calls.contains("BatchMode"), "{calls}");
+}
+
+/// Get the canonical path of a directory as a string, as it's used in configs.
+fn canonical(path: &Path) -> String {
+    fs::canonicalize(path)
+        .unwrap()
+        .to_string_lossy()
+        .to_string()
+}
+
+/// Get the paths of all repositories that are known to geil.
+fn known_repositories(geil: &Geil) -> BTreeSet<PathBuf> {
+    geil.state()
+        .repositories
+        .into_iter()
+        .map(|repo| repo.path)
+        .collect()
+}
+
+#[test]
+fn watch_and_unwatch() {
+    let remote = Remote::new();
+    let projects = TempDir::new().expect("Failed to create temporary directory");
+    let first = remote.clone_into(projects.path(), "first");
+    let second = remote.clone_into(projects.path(), "nested/second");
+    let other = TempDir::new().expect("Failed to create temporary directory");
+    let outside = remote.clone_into(other.path(), "outside");
+
+    let geil = Geil::new();
+    geil.run(&["add", &outside.to_string_lossy()]);
+    geil.run(&["watch", &projects.path().to_string_lossy()]);
+
+    // All repositories inside of the directory are discovered right away.
+    let expected: BTreeSet<PathBuf> = [&first, &second, &outside]
+        .into_iter()
+        .map(|repo| fs::canonicalize(repo).unwrap())
+        .collect();
+    assert_eq!(known_repositories(&geil), expected);
+    let watched: Vec<PathBuf> = geil.state().watched.iter().map(|w| w.path()).collect();
+    assert_eq!(watched, vec![fs::canonicalize(projects.path()).unwrap()]);
+
+    // New repositories are found by the next scan.
+    let third = remote.clone_into(projects.path(), "third");
+    geil.run(&["scan"]);
+    assert!(known_repositories(&geil).contains(&fs::canonicalize(&third).unwrap()));
+
+    // Discovered repositories can be added explicitly as well.
+    let kept = remote.clone_into(projects.path(), "kept");
+    geil.run(&["scan"]);
+    geil.run(&["add", &kept.to_string_lossy()]);
+
+    // Only the repositories that are known through the directory are forgotten.
+    geil.run(&["unwatch", &projects.path().to_string_lossy()]);
+    assert!(geil.state().watched.is_empty());
+    assert_eq!(
+        known_repositories(&geil),
+        BTreeSet::from([
+            fs::canonicalize(&kept).unwrap(),
+            fs::canonicalize(&outside).unwrap()
+        ])
+    );
+}
+
+/// Get the names of all repositories in the json output of geil.
+fn json_names(output: &str) -> BTreeSet<String> {
+    let repositories: Vec<serde_json::Value> =
+        serde_json::from_str(output).expect("Invalid json output");
+
+    repositories
+        .iter()
+        .map(|repo| repo["name"].as_str().unwrap().to_string())
+        .collect()
+}
+
+/// Create a sandbox watching `projects/work/{api,web}-service` and `projects/dotfiles`.
+/// The work repositories are in the `work` group.
+fn grouped_projects(remote: &Remote, projects: &Path) -> Geil {
+    for path in ["work/api-service", "work/web-service", "dotfiles"] {
+        remote.clone_into(projects, path);
+    }
+    fs::write(projects.join("dotfiles/file"), "changed\n").unwrap();
+
+    let projects = canonical(projects);
+    Geil::with_config(&format!(
+        "watched:\n- {projects}\nrepositories: []\n\
+         groups:\n- name: work\n  paths:\n  - {projects}/work\n"
+    ))
+}
+
+#[test]
+fn machine_readable_output() {
+    let remote = Remote::new();
+    let projects = TempDir::new().expect("Failed to create temporary directory");
+    let geil = grouped_projects(&remote, projects.path());
+
+    // Machine-readable formats contain all repositories, not only the interesting ones.
+    let output = geil.run(&["check", "--output", "json"]);
+    let repositories: Vec<serde_json::Value> =
+        serde_json::from_str(&output).expect("Invalid json output");
+    let findings: BTreeMap<&str, &serde_json::Value> = repositories
+        .iter()
+        .map(|repo| (repo["name"].as_str().unwrap(), &repo["findings"]))
+        .collect();
+    assert_eq!(
+        findings,
+        BTreeMap::from([
+            ("api-service", &serde_json::json!([])),
+            ("dotfiles", &serde_json::json!(["local_changes"])),
+            ("web-service", &serde_json::json!([])),
+        ])
+    );
+
+    let output = geil.run(&["check", "--output", "yaml"]);
+    let repositories: Vec<serde_yaml::Value> =
+        serde_yaml::from_str(&output).expect("Invalid yaml output");
+    let dotfiles = repositories
+        .iter()
+        .find(|repo| repo["name"].as_str() == Some("dotfiles"))
+        .expect("Missing repository in yaml output");
+    assert_eq!(
+        dotfiles["findings"],
+        serde_yaml::from_str::<serde_yaml::Value>("[local_changes]").unwrap()
+    );
+    assert_eq!(dotfiles["changes"]["modified"].as_u64(), Some(1));
+
+    // Only the documented fields are part of the output.
+    let fields: BTreeSet<&str> = repositories[0]
+        .as_mapping()
+        .unwrap()
+        .keys()
+        .filter_map(|key| key.as_str())
+        .collect();
+    assert_eq!(
+        fields,
+        BTreeSet::from([
+            "ahead",
+            "behind",
+            "behind_default",
+            "branches",
+            "changes",
+            "default_branch",
+            "error",
+            "findings",
+            "groups",
+            "name",
+            "outcome",
+            "path",
+            "stashed",
+            "switched_from",
+        ])
+    );
+
+    // Messages about the first run don't end up in the output.
+    let output = Geil::new().run(&["check", "--output", "json"]);
+    serde_json::from_str::<serde_json::Value>(&output).expect("Invalid json output");
+}
+
+#[test]
+fn groups_and_filters() {
+    let remote = Remote::new();
+    let projects = TempDir::new().expect("Failed to create temporary directory");
+    let geil = grouped_projects(&remote, projects.path());
+    let names = |args: &[&str]| {
+        let args = [&["check", "--output", "json"], args].concat();
+        json_names(&geil.run(&args))
+    };
+    let set = |names: &[&str]| -> BTreeSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    };
+
+    assert_eq!(names(&[]), set(&["api-service", "dotfiles", "web-service"]));
+    assert_eq!(
+        names(&["--group", "work"]),
+        set(&["api-service", "web-service"])
+    );
+    assert_eq!(names(&["--exclude-group", "work"]), set(&["dotfiles"]));
+    assert_eq!(
+        names(&["--filter", "*-service"]),
+        set(&["api-service", "web-service"])
+    );
+    assert_eq!(names(&["--filter", "re:api"]), set(&["api-service"]));
+    assert_eq!(
+        names(&["--group", "work", "--filter", "re:web"]),
+        set(&["web-service"])
+    );
+
+    // Paths select the repositories inside of them or the repository containing them.
+    let work = projects.path().join("work");
+    assert_eq!(
+        names(&[&work.to_string_lossy()]),
+        set(&["api-service", "web-service"])
+    );
+    let inside = projects.path().join("dotfiles/file");
+    assert_eq!(names(&[&inside.to_string_lossy()]), set(&["dotfiles"]));
+}
+
+#[test]
+fn paths_through_symlinks() {
+    let remote = Remote::new();
+    let projects = TempDir::new().expect("Failed to create temporary directory");
+    let other = TempDir::new().expect("Failed to create temporary directory");
+    let repo = remote.clone_into(other.path(), "linked");
+    fs::write(repo.join("file"), "changed\n").unwrap();
+    symlink(other.path(), projects.path().join("other")).unwrap();
+    let geil = Geil::with_config(&format!(
+        "watched:\n- {}\nrepositories: []\n",
+        canonical(projects.path())
+    ));
+
+    // The repository is known by the path it has been found at.
+    geil.run(&["scan"]);
+    let found = fs::canonicalize(projects.path())
+        .unwrap()
+        .join("other/linked");
+    assert_eq!(known_repositories(&geil), BTreeSet::from([found.clone()]));
+
+    // It's still selected by its real path, or any path inside of it.
+    for path in [&repo, &repo.join("file"), &found] {
+        let output = geil.run(&["check", "--output", "json", &path.to_string_lossy()]);
+        assert_eq!(json_names(&output), BTreeSet::from(["linked".to_string()]));
+    }
+}
+
+#[test]
+fn export_and_clone() {
+    let remote = Remote::new();
+    let work = TempDir::new().expect("Failed to create temporary directory");
+    let projects = work.path().join("projects");
+    remote.clone_into(&projects, "app");
+    remote.clone_into(&projects, "nested/lib");
+    let app = canonical(&projects.join("app"));
+    let geil = Geil::with_config(&format!(
+        "watched:\n- {}\nrepositories: []\n\
+         hooks:\n- path: {app}\n  command: touch hooked\n",
+        canonical(&projects)
+    ));
+
+    // Paths are stored relative to their watched directory.
+    let manifest_path = geil.dir.path().join("manifest.yml");
+    geil.run(&["export", &manifest_path.to_string_lossy()]);
+    let mut manifest = Manifest::load(&manifest_path).unwrap();
+    manifest.repositories.sort_by(|a, b| a.path.cmp(&b.path));
+    let entries: Vec<(&Path, Option<&str>)> = manifest
+        .repositories
+        .iter()
+        .map(|entry| (entry.path.as_path(), entry.hook.as_deref()))
+        .collect();
+    assert_eq!(
+        entries,
+        vec![
+            (Path::new("app"), Some("touch hooked")),
+            (Path::new("nested/lib"), None),
+        ]
+    );
+    assert_eq!(
+        manifest.repositories[0].remotes,
+        BTreeMap::from([("origin".to_string(), remote.url())])
+    );
+
+    // Set up another machine, where the watched directory lives somewhere else.
+    // Repositories are cloned into the watched directory with the same name.
+    let machine = TempDir::new().expect("Failed to create temporary directory");
+    let other_projects = machine.path().join("projects");
+    fs::create_dir(&other_projects).unwrap();
+    let other = Geil::with_config(&format!(
+        "watched:\n- {}\nrepositories: []\n",
+        other_projects.to_string_lossy()
+    ));
+
+    // Hooks are only registered, if that's explicitly requested.
+    other.run(&["clone", &manifest_path.to_string_lossy()]);
+    let cloned = other_projects.join("nested/lib");
+    assert_eq!(git(&cloned, &["remote", "get-url", "origin"]), remote.url());
+    assert_eq!(
+        known_repositories(&other),
+        BTreeSet::from([other_projects.join("app"), cloned])
+    );
+    assert!(other.state().hooks.is_empty());
+
+    fs::remove_dir_all(other_projects.join("app")).unwrap();
+    other.run(&["clone", "--with-hooks", &manifest_path.to_string_lossy()]);
+    let hooks: Vec<(PathBuf, String)> = other
+        .state()
+        .hooks
+        .iter()
+        .map(|hook| (hook.path(), hook.command.clone()))
+        .collect();
+    assert_eq!(
+        hooks,
+        vec![(other_projects.join("app"), "touch hooked".to_string())]
+    );
+
+    // Repositories are never cloned outside of the watched directories.
+    let unwatched = Geil::new();
+    assert!(
+        !unwatched
+            .command(&["clone", &manifest_path.to_string_lossy()])
+            .status
+            .success()
+    );
+    assert!(known_repositories(&unwatched).is_empty());
+}